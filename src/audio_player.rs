@@ -1,9 +1,21 @@
-use std::io::Cursor;
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use actix::prelude::*;
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
 use anyhow::Result;
 
+use crate::conversation::Lifecycle;
+
+/// Number of samples the playback level is averaged over
+const METER_WINDOW: usize = 1024;
+
 #[derive(Message)]
 #[rtype(result = "Result<()>")]
 pub struct Audio(pub Vec<u8>);
@@ -12,10 +24,173 @@ pub struct Audio(pub Vec<u8>);
 #[rtype(result = "Result<Status>")]
 pub struct StatusRequest;
 
-#[derive(PartialEq)]
-pub enum Status {
-    Idle,
-    Busy,
+/// Stop whatever is in progress and drop anything queued.
+/// Actors pass it on to the actors downstream of them.
+#[derive(Message, Clone, Copy)]
+#[rtype(result = "()")]
+pub struct Cancel;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    /// Jobs that have been accepted but haven't finished yet
+    pub queue_depth: usize,
+    /// The oldest job still in flight
+    pub current_job: Option<String>,
+    /// Time since a job was last started or finished
+    pub since_last_activity: Duration,
+}
+
+impl Status {
+    pub fn is_idle(&self) -> bool {
+        self.queue_depth == 0
+    }
+}
+
+/// Tracks the jobs an actor has in flight, for answering `StatusRequest`.
+///
+/// Handlers call `start` when they accept a job and `finish` once its future has
+/// completed, eg. from the `map` of a `ResponseActFuture`.
+pub struct Activity {
+    jobs: Vec<(usize, String)>,
+    next_id: usize,
+    last_activity: Instant,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            jobs: vec![],
+            next_id: 0,
+            last_activity: Instant::now(),
+        }
+    }
+}
+
+impl Activity {
+    /// Returns an id to pass to `finish`.
+    pub fn start(&mut self, job: impl Into<String>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push((id, job.into()));
+        self.last_activity = Instant::now();
+        id
+    }
+
+    pub fn finish(&mut self, id: usize) {
+        self.jobs.retain(|(job_id, _)| *job_id != id);
+        self.last_activity = Instant::now();
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            queue_depth: self.jobs.len(),
+            current_job: self.jobs.first().map(|(_, job)| job.clone()),
+            since_last_activity: self.last_activity.elapsed(),
+        }
+    }
+}
+
+/// Lets other threads see whether anything is playing and how loud it is,
+/// eg. so that STT can tell the assistant's own voice from the user's.
+#[derive(Clone, Default)]
+pub struct PlaybackMonitor {
+    queued: Arc<AtomicUsize>,
+    /// RMS of the most recent samples, as f32 bits
+    level: Arc<AtomicU32>,
+}
+
+impl PlaybackMonitor {
+    pub fn is_playing(&self) -> bool {
+        self.queued.load(Ordering::SeqCst) > 0
+    }
+
+    /// Loudness of what's playing right now, from 0.0 to 1.0.
+    pub fn level(&self) -> f32 {
+        if self.is_playing() {
+            f32::from_bits(self.level.load(Ordering::Relaxed))
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Passes samples through while measuring their level for the monitor.
+struct Metered<S> {
+    source: S,
+    monitor: PlaybackMonitor,
+    events: Option<Recipient<Lifecycle>>,
+    started: bool,
+    sum_of_squares: f32,
+    count: usize,
+}
+
+impl<S> Metered<S> {
+    fn new(source: S, monitor: PlaybackMonitor, events: Option<Recipient<Lifecycle>>) -> Self {
+        monitor.queued.fetch_add(1, Ordering::SeqCst);
+        Self {
+            source,
+            monitor,
+            events,
+            started: false,
+            sum_of_squares: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl<S: Source<Item = i16>> Iterator for Metered<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.source.next()?;
+
+        if !self.started {
+            self.started = true;
+            if let Some(events) = &self.events {
+                events.do_send(Lifecycle::PlaybackStarted);
+            }
+        }
+
+        let value = sample as f32 / i16::MAX as f32;
+        self.sum_of_squares += value * value;
+        self.count += 1;
+        if self.count == METER_WINDOW {
+            let rms = (self.sum_of_squares / self.count as f32).sqrt();
+            self.monitor.level.store(rms.to_bits(), Ordering::Relaxed);
+            self.sum_of_squares = 0.0;
+            self.count = 0;
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = i16>> Source for Metered<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+// Covers both finishing and being thrown away by `Sink::stop`
+impl<S> Drop for Metered<S> {
+    fn drop(&mut self) {
+        self.monitor.queued.fetch_sub(1, Ordering::SeqCst);
+        if let Some(events) = &self.events {
+            events.do_send(Lifecycle::UtterancePlayed);
+        }
+    }
 }
 
 pub struct AudioPlayerActor {
@@ -23,12 +198,20 @@ pub struct AudioPlayerActor {
     // Don't drop the stream and stream handle for as long as the Sink lives!
     #[allow(dead_code)]
     output_stream: OutputStream,
-    #[allow(dead_code)]
     output_stream_handle: OutputStreamHandle,
+    monitor: PlaybackMonitor,
+    events: Option<Recipient<Lifecycle>>,
+    last_activity: Instant,
 }
 
 impl Default for AudioPlayerActor {
     fn default() -> Self {
+        Self::with(PlaybackMonitor::default())
+    }
+}
+
+impl AudioPlayerActor {
+    pub fn with(monitor: PlaybackMonitor) -> Self {
         let (output_stream, output_stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&output_stream_handle).unwrap();
 
@@ -36,8 +219,17 @@ impl Default for AudioPlayerActor {
             sink,
             output_stream,
             output_stream_handle,
+            monitor,
+            events: None,
+            last_activity: Instant::now(),
         }
     }
+
+    /// Report every utterance as it starts playing and once it has finished.
+    pub fn events(mut self, events: Recipient<Lifecycle>) -> Self {
+        self.events = Some(events);
+        self
+    }
 }
 
 impl Actor for AudioPlayerActor {
@@ -50,10 +242,12 @@ impl Handler<Audio> for AudioPlayerActor {
     fn handle(&mut self, msg: Audio, _ctx: &mut SyncContext<Self>) -> Self::Result {
         println!("Audio Player : Received audio data");
 
+        self.last_activity = Instant::now();
+
         let cursor = Cursor::new(msg.0);
-        let source = Decoder::new(cursor).unwrap();
+        let source = Decoder::new(cursor)?;
 
-        self.sink.append(source);
+        self.sink.append(Metered::new(source, self.monitor.clone(), self.events.clone()));
 
         Ok(())
     }
@@ -68,10 +262,26 @@ impl Handler<StatusRequest> for AudioPlayerActor {
         //     self.sink.empty()
         // );
 
-        if self.sink.empty() {
-            Ok(Status::Idle)
-        } else {
-            Ok(Status::Busy)
+        Ok(Status {
+            queue_depth: self.sink.len(),
+            current_job: (!self.sink.empty()).then(|| "Playing audio".to_string()),
+            since_last_activity: self.last_activity.elapsed(),
+        })
+    }
+}
+
+impl Handler<Cancel> for AudioPlayerActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Cancel, _ctx: &mut SyncContext<Self>) -> Self::Result {
+        println!("Audio Player : Cancelled");
+        self.last_activity = Instant::now();
+
+        // A stopped sink stays stopped, so start over with a fresh one
+        self.sink.stop();
+        match Sink::try_new(&self.output_stream_handle) {
+            Ok(sink) => self.sink = sink,
+            Err(e) => eprintln!("Audio Player : Unable to create a new sink: {}", e),
         }
     }
 }
@@ -0,0 +1,216 @@
+use std::{collections::VecDeque, fs, path::Path, sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, Result};
+use async_openai::{
+    config::OpenAIConfig,
+    types::{
+        ChatCompletionFunctions, ChatCompletionRequestMessage, CreateChatCompletionRequestArgs,
+        FunctionCallStream,
+    },
+    Client,
+};
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use serde::Deserialize;
+
+/// Everything a backend needs to produce the next reply.
+pub struct ChatRequest {
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// Functions the model may call. Empty if function calling is off.
+    pub functions: Vec<ChatCompletionFunctions>,
+}
+
+/// A piece of a streamed reply.
+#[derive(Debug, Default, Clone)]
+pub struct Delta {
+    pub content: Option<String>,
+    pub function_call: Option<FunctionCallStream>,
+}
+
+pub type DeltaStream = BoxStream<'static, Result<Delta>>;
+
+/// Anything that can stream a chat completion.
+pub trait ChatBackend {
+    fn stream(&self, request: ChatRequest) -> BoxFuture<'static, Result<DeltaStream>>;
+}
+
+/// OpenAI, or any server that speaks its API (llama.cpp server, vLLM, ...).
+pub struct OpenAiBackend {
+    client: Client<OpenAIConfig>,
+    model: String,
+}
+
+impl OpenAiBackend {
+    /// Reads the key from `OPENAI_API_KEY`.
+    pub fn new(model: &str) -> Self {
+        Self {
+            client: Client::new(),
+            model: model.to_string(),
+        }
+    }
+
+    /// An OpenAI-compatible server at `base_url`, eg. "http://localhost:8080/v1".
+    pub fn with_base_url(base_url: &str, api_key: &str, model: &str) -> Self {
+        let config = OpenAIConfig::new()
+            .with_api_base(base_url)
+            .with_api_key(api_key);
+        Self {
+            client: Client::with_config(config),
+            model: model.to_string(),
+        }
+    }
+}
+
+impl ChatBackend for OpenAiBackend {
+    fn stream(&self, request: ChatRequest) -> BoxFuture<'static, Result<DeltaStream>> {
+        let client = self.client.clone();
+        let model = self.model.clone();
+
+        Box::pin(async move {
+            let mut args = CreateChatCompletionRequestArgs::default();
+            args.model(model).messages(request.messages).stream(true);
+            if !request.functions.is_empty() {
+                args.functions(request.functions);
+            }
+
+            let response = client.chat().create_stream(args.build()?).await?;
+
+            let deltas = response.filter_map(|result| async move {
+                match result {
+                    Ok(mut response) => {
+                        let choice = response.choices.pop()?;
+                        Some(Ok(Delta {
+                            content: choice.delta.content,
+                            function_call: choice.delta.function_call,
+                        }))
+                    }
+                    Err(e) => Some(Err(anyhow::Error::from(e))),
+                }
+            });
+
+            Ok(deltas.boxed())
+        })
+    }
+}
+
+/// One scripted reply. Either give the exact `tokens`, or give the whole `text`
+/// and let it be cut into `chunk_size` character chunks.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScriptedReply {
+    pub tokens: Vec<String>,
+    pub text: Option<String>,
+    pub chunk_size: Option<usize>,
+    /// Delay before each chunk, to mimic a real stream
+    pub delay_ms: u64,
+    pub function_call: Option<ScriptedFunctionCall>,
+    /// Fail with this after everything else, as a dropped connection would
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptedFunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Script {
+    replies: Vec<ScriptedReply>,
+}
+
+impl ScriptedReply {
+    fn deltas(self) -> Vec<Delta> {
+        let chunk_size = self.chunk_size.unwrap_or(1).max(1);
+        let chunk = |text: &str| -> Vec<String> {
+            let chars: Vec<char> = text.chars().collect();
+            chars.chunks(chunk_size).map(|chunk| chunk.iter().collect()).collect()
+        };
+
+        let mut tokens = self.tokens;
+        if let Some(text) = &self.text {
+            tokens.extend(chunk(text));
+        }
+
+        let mut deltas: Vec<Delta> = tokens
+            .into_iter()
+            .map(|token| Delta {
+                content: Some(token),
+                function_call: None,
+            })
+            .collect();
+
+        // The name comes first, then the arguments in pieces
+        if let Some(function_call) = self.function_call {
+            deltas.push(Delta {
+                content: None,
+                function_call: Some(FunctionCallStream {
+                    name: Some(function_call.name),
+                    arguments: None,
+                }),
+            });
+            deltas.extend(chunk(&function_call.arguments).into_iter().map(|arguments| Delta {
+                content: None,
+                function_call: Some(FunctionCallStream {
+                    name: None,
+                    arguments: Some(arguments),
+                }),
+            }));
+        }
+
+        deltas
+    }
+}
+
+/// Replays scripted replies, one per request, without touching the network.
+#[derive(Default)]
+pub struct MockBackend {
+    replies: Mutex<VecDeque<ScriptedReply>>,
+}
+
+impl MockBackend {
+    /// Each reply is streamed as the given tokens.
+    pub fn new(replies: Vec<Vec<String>>) -> Self {
+        Self::scripted(
+            replies
+                .into_iter()
+                .map(|tokens| ScriptedReply {
+                    tokens,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    pub fn scripted(replies: Vec<ScriptedReply>) -> Self {
+        Self {
+            replies: Mutex::new(replies.into()),
+        }
+    }
+
+    /// Load a script from a JSON file of the form `{ "replies": [ ... ] }`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let script: Script = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Self::scripted(script.replies))
+    }
+}
+
+impl ChatBackend for MockBackend {
+    fn stream(&self, _request: ChatRequest) -> BoxFuture<'static, Result<DeltaStream>> {
+        let reply = self.replies.lock().unwrap().pop_front();
+
+        Box::pin(async move {
+            let mut reply = reply.ok_or_else(|| anyhow!("Mock backend has no more replies"))?;
+            let delay = Duration::from_millis(reply.delay_ms);
+            let error = reply.error.take().map(|error| Err(anyhow!(error)));
+            let deltas = futures::stream::iter(reply.deltas())
+                .then(move |delta| async move {
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    Ok::<_, anyhow::Error>(delta)
+                })
+                .chain(futures::stream::iter(error));
+            Ok(deltas.boxed())
+        })
+    }
+}
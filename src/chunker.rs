@@ -0,0 +1,329 @@
+use std::{ops::Range, path::Path, sync::Arc};
+
+use anyhow::{ensure, Result};
+use tiktoken_rs::CoreBPE;
+
+use crate::{document_loader::Document, sentence};
+
+/// Tokens per chunk, unless a strategy says otherwise.
+///
+/// Sizes are counted with cl100k, but the embedding model cuts text off after 256
+/// of its own WordPiece tokens, [CLS] and [SEP] included. WordPiece needs more
+/// tokens than cl100k for the same text, up to about twice as many for code,
+/// identifiers and languages other than English, so this leaves that much room
+/// rather than letting the ends of chunks go unembedded.
+pub const DEFAULT_SIZE: usize = 128;
+
+/// Files with these extensions are split with the code strategy
+const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "js", "jsx", "ts", "tsx", "go", "java", "kt", "c", "h", "cpp", "hpp", "cs", "rb",
+    "swift", "scala", "php", "sh",
+];
+
+/// How to cut a document into chunks. Sizes are in tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Windows of `size` tokens, each repeating the last `overlap` tokens of the one before
+    Tokens { size: usize, overlap: usize },
+    /// Split on headings, then paragraphs, then sentences, until every piece fits
+    Recursive { size: usize },
+    /// Split between top-level items, then between lines, until every piece fits
+    Code { size: usize },
+}
+
+impl Strategy {
+    fn size(&self) -> usize {
+        match *self {
+            Strategy::Tokens { size, .. }
+            | Strategy::Recursive { size }
+            | Strategy::Code { size } => size,
+        }
+    }
+
+    fn validate(self) -> Result<Self> {
+        ensure!(self.size() > 0, "Chunk size must be more than 0");
+        if let Strategy::Tokens { size, overlap } = self {
+            ensure!(
+                overlap < size,
+                "Chunk overlap {} must be less than the chunk size {}",
+                overlap,
+                size
+            );
+        }
+        Ok(self)
+    }
+}
+
+/// A piece of a document, small enough to embed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// `Document::id` of the document it was cut from
+    pub parent: String,
+    /// Byte offsets into the parent's text, for citing it
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Finds the byte offsets to split a range at, from coarse to fine.
+type Splitter = fn(&str, Range<usize>) -> Vec<usize>;
+
+const PROSE: &[Splitter] = &[headings, paragraphs, sentences];
+const CODE: &[Splitter] = &[items, lines];
+
+#[derive(Clone)]
+pub struct Chunker {
+    /// For prose
+    strategy: Strategy,
+    /// For source files
+    code: Strategy,
+    bpe: Arc<CoreBPE>,
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::Recursive { size: DEFAULT_SIZE },
+            code: Strategy::Code { size: DEFAULT_SIZE },
+            bpe: Arc::new(tiktoken_rs::cl100k_base().expect("Cannot load tokenizer")),
+        }
+    }
+}
+
+impl Chunker {
+    pub fn new(strategy: Strategy) -> Result<Self> {
+        Ok(Self {
+            strategy: strategy.validate()?,
+            ..Self::default()
+        })
+    }
+
+    /// Use a different strategy for source files.
+    pub fn code(mut self, strategy: Strategy) -> Result<Self> {
+        self.code = strategy.validate()?;
+        Ok(self)
+    }
+
+    pub fn chunk(&self, document: &Document) -> Vec<Chunk> {
+        let strategy = if is_code(&document.metadata().source) {
+            self.code
+        } else {
+            self.strategy
+        };
+
+        let text = document.text();
+        self.spans(text, strategy)
+            .into_iter()
+            .filter_map(|span| trim(text, span))
+            .map(|span| Chunk {
+                parent: document.id().to_string(),
+                text: text[span.clone()].to_string(),
+                start: span.start,
+                end: span.end,
+            })
+            .collect()
+    }
+
+    /// Byte ranges of `text` to cut it into with `strategy`.
+    pub fn spans(&self, text: &str, strategy: Strategy) -> Vec<Range<usize>> {
+        let all = 0..text.len();
+        let (size, splitters) = match strategy {
+            Strategy::Tokens { size, overlap } => return self.windows(text, all, size, overlap),
+            Strategy::Recursive { size } => (size, PROSE),
+            Strategy::Code { size } => (size, CODE),
+        };
+
+        let mut spans = vec![];
+        self.split(text, all, size, splitters, &mut spans);
+        spans
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+
+    /// Splits with the first splitter, merging neighbouring pieces back together while
+    /// they fit. Pieces that are still too big go to the next splitter, and after the
+    /// last one they're cut into token windows.
+    fn split(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        size: usize,
+        splitters: &[Splitter],
+        spans: &mut Vec<Range<usize>>,
+    ) {
+        if self.count(&text[range.clone()]) <= size {
+            spans.push(range);
+            return;
+        }
+        let Some((splitter, finer)) = splitters.split_first() else {
+            spans.extend(self.windows(text, range, size, 0));
+            return;
+        };
+
+        let mut current: Option<(Range<usize>, usize)> = None;
+        let cuts = splitter(text, range.clone());
+        for piece in pieces(range, cuts) {
+            let tokens = self.count(&text[piece.clone()]);
+            if tokens > size {
+                if let Some((merged, _)) = current.take() {
+                    spans.push(merged);
+                }
+                self.split(text, piece, size, finer, spans);
+                continue;
+            }
+
+            current = match current.take() {
+                Some((merged, merged_tokens)) if merged_tokens + tokens <= size => {
+                    Some((merged.start..piece.end, merged_tokens + tokens))
+                }
+                Some((merged, _)) => {
+                    spans.push(merged);
+                    Some((piece, tokens))
+                }
+                None => Some((piece, tokens)),
+            };
+        }
+        if let Some((merged, _)) = current {
+            spans.push(merged);
+        }
+    }
+
+    /// Windows of whole words, as many as fit in `size` tokens.
+    fn windows(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        size: usize,
+        overlap: usize,
+    ) -> Vec<Range<usize>> {
+        let words = words(text, range);
+        let counts: Vec<usize> = words.iter().map(|word| self.count(&text[word.clone()])).collect();
+
+        let mut windows = vec![];
+        let mut start = 0;
+        while start < words.len() {
+            // Always take at least one word, even if it's too big on its own
+            let mut end = start;
+            let mut tokens = 0;
+            while end < words.len() && (end == start || tokens + counts[end] <= size) {
+                tokens += counts[end];
+                end += 1;
+            }
+            windows.push(words[start].start..words[end - 1].end);
+            if end == words.len() {
+                break;
+            }
+
+            // Step back over as many words as fit in the overlap, but always move forward
+            let mut next = end;
+            let mut repeated = 0;
+            while next > start + 1 && repeated + counts[next - 1] <= overlap {
+                repeated += counts[next - 1];
+                next -= 1;
+            }
+            start = next;
+        }
+        windows
+    }
+}
+
+fn is_code(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| CODE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Shrinks a span to leave out whitespace at either end, or drops it if that's all there is.
+fn trim(text: &str, span: Range<usize>) -> Option<Range<usize>> {
+    let slice = &text[span.clone()];
+    let trimmed = slice.trim_start();
+    let start = span.start + (slice.len() - trimmed.len());
+    let end = start + trimmed.trim_end().len();
+    (start < end).then_some(start..end)
+}
+
+/// Cuts a range at the given offsets.
+fn pieces(range: Range<usize>, cuts: Vec<usize>) -> Vec<Range<usize>> {
+    let mut pieces = vec![];
+    let mut start = range.start;
+    for cut in cuts {
+        if cut > start && cut < range.end {
+            pieces.push(start..cut);
+            start = cut;
+        }
+    }
+    pieces.push(start..range.end);
+    pieces
+}
+
+/// Each word along with the whitespace after it.
+fn words(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut starts = vec![range.start];
+    let mut previous = None;
+    for (i, c) in text[range.clone()].char_indices() {
+        if previous.map_or(false, char::is_whitespace) && !c.is_whitespace() {
+            starts.push(range.start + i);
+        }
+        previous = Some(c);
+    }
+    pieces(range, starts)
+}
+
+fn line_starts(text: &str, range: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+    text[range.clone()]
+        .match_indices('\n')
+        .map(move |(i, _)| range.start + i + 1)
+}
+
+fn line_at(text: &str, start: usize) -> &str {
+    text[start..].lines().next().unwrap_or_default()
+}
+
+fn headings(text: &str, range: Range<usize>) -> Vec<usize> {
+    line_starts(text, range)
+        .filter(|&start| {
+            let line = line_at(text, start);
+            let level = line.chars().take_while(|c| *c == '#').count();
+            (1..=6).contains(&level) && line[level..].starts_with(' ')
+        })
+        .collect()
+}
+
+/// At the start of every blank line, so each blank line begins the next piece,
+/// where it's trimmed off.
+fn paragraphs(text: &str, range: Range<usize>) -> Vec<usize> {
+    line_starts(text, range)
+        .filter(|&start| line_at(text, start).trim().is_empty())
+        .collect()
+}
+
+fn sentences(text: &str, range: Range<usize>) -> Vec<usize> {
+    let mut cuts = vec![];
+    let mut start = range.start;
+    while let Some(end) = sentence::next_boundary(&text[start..range.end]) {
+        start += end;
+        cuts.push(start);
+    }
+    cuts
+}
+
+/// Unindented lines after a blank line, which in most languages start a new item.
+fn items(text: &str, range: Range<usize>) -> Vec<usize> {
+    let mut cuts = vec![];
+    let mut after_blank = false;
+    for start in line_starts(text, range) {
+        let line = line_at(text, start);
+        let unindented = line.starts_with(|c: char| !c.is_whitespace() && !matches!(c, '}' | ')' | ']'));
+        if after_blank && unindented {
+            cuts.push(start);
+        }
+        after_blank = line.trim().is_empty();
+    }
+    cuts
+}
+
+fn lines(text: &str, range: Range<usize>) -> Vec<usize> {
+    line_starts(text, range).collect()
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Hex digits in a citation id after the "c"
+const ID_DIGITS: usize = 8;
+
+/// Where a cited chunk came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    pub path: String,
+    /// The document the chunk was cut from. A file split into pages or sections
+    /// makes one document per part, eg. "notes.pdf#2".
+    #[serde(default)]
+    pub document: String,
+    /// The page or section of the file, eg. "page 3"
+    #[serde(default)]
+    pub location: Option<String>,
+    /// Byte offsets of the chunk in its document, not in the file
+    pub start: usize,
+    pub end: usize,
+    pub score: f32,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(location) = &self.location {
+            write!(f, ", {}", location)?;
+        }
+        write!(f, ", bytes {}..{} (score {:.2})", self.start, self.end, self.score)
+    }
+}
+
+impl Source {
+    /// Made from where the chunk is, so it's the same in every session.
+    pub fn id(&self) -> String {
+        let location = self.location.as_deref().unwrap_or_default();
+        let key = format!("{}#{}#{}#{}..{}", self.path, self.document, location, self.start, self.end);
+        let digest = Sha256::digest(key.as_bytes());
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("c{}", &hex[..ID_DIGITS])
+    }
+}
+
+/// Hands out citation ids for retrieved chunks and looks them up again.
+///
+/// Shared between whoever feeds chunks to the LLM and whoever reads its answers.
+/// The same chunk always gets the same id, so the LLM can cite it from earlier turns,
+/// and from earlier sessions once their sources are restored.
+#[derive(Clone, Default)]
+pub struct Citations(Arc<Mutex<HashMap<String, Source>>>);
+
+impl Citations {
+    /// Knows the sources cited in a previous session.
+    pub fn restore(sources: Vec<Source>) -> Self {
+        let citations = Self::default();
+        for source in sources {
+            citations.cite(source);
+        }
+        citations
+    }
+
+    /// The id to label the chunk with, eg. "c1f3a9b2e".
+    pub fn cite(&self, source: Source) -> String {
+        let id = source.id();
+        // Keep the latest score
+        self.0.lock().unwrap().insert(id.clone(), source);
+        id
+    }
+
+    pub fn resolve(&self, id: &str) -> Option<Source> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+}
+
+/// Takes citation markers like "[c1f3a9b2e]" out of `text`.
+/// Returns the cleaned up text and the ids that were cited, in order.
+pub fn strip_markers(text: &str) -> (String, Vec<String>) {
+    let mut stripped = String::with_capacity(text.len());
+    let mut ids = vec![];
+
+    let mut rest = text;
+    while let Some(open) = rest.find('[') {
+        let marker = rest[open + 1..]
+            .find(']')
+            .map(|close| &rest[open + 1..open + 1 + close])
+            .filter(|marker| is_marker(marker));
+
+        match marker {
+            Some(marker) => {
+                // Don't leave a space behind before punctuation, eg. "blue [c1f3a9b2e]."
+                stripped.push_str(rest[..open].trim_end());
+                ids.extend(marker.split(',').map(|id| id.trim().to_string()));
+                rest = &rest[open + marker.len() + 2..];
+                if stripped.is_empty() {
+                    // Nor a space at the start, eg. "[c1f3a9b2e] It rains."
+                    rest = rest.trim_start();
+                } else {
+                    let joined = rest.starts_with(|c: char| c.is_whitespace() || c.is_ascii_punctuation());
+                    if !joined && !rest.is_empty() {
+                        stripped.push(' ');
+                    }
+                }
+            }
+            None => {
+                stripped.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+
+    (stripped, ids)
+}
+
+/// One or more ids, eg. "c1f3a9b2e" or "c1f3a9b2e, c07d4e611".
+fn is_marker(marker: &str) -> bool {
+    marker.split(',').all(|id| {
+        let id = id.trim();
+        id.len() == ID_DIGITS + 1
+            && id.starts_with('c')
+            && id[1..].chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
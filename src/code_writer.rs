@@ -20,8 +20,8 @@ impl Handler<Code> for CodeWriter {
 
     fn handle(&mut self, msg: Code, _ctx: &mut Self::Context) -> Self::Result {
         Box::pin(async move {
-            let mut file = File::create(msg.filename).await.unwrap();
-            file.write_all(msg.content.as_bytes()).await.unwrap();
+            let mut file = File::create(msg.filename).await?;
+            file.write_all(msg.content.as_bytes()).await?;
             Ok(())
         })
     }
@@ -0,0 +1,208 @@
+use actix::prelude::*;
+use async_openai::types::Role;
+
+use crate::{
+    interpreter::GetObservations,
+    llm::{ChatMessage, INITIAL_PROMPT},
+    stt::SttAction,
+};
+
+/// What the actors in the pipeline report as they go.
+///
+/// Whoever hands work on to the next actor reports it as queued, rather than the actor
+/// that picks it up. That way it's always in the mailbox before `LlmFinished`.
+#[derive(Message, Debug, Clone, Copy, PartialEq)]
+#[rtype(result = "()")]
+pub enum Lifecycle {
+    LlmStarted,
+    LlmFinished,
+    UtteranceQueued,
+    /// Some audio has started coming out of the speakers
+    PlaybackStarted,
+    /// Played to the end, or dropped because of a cancel or a TTS error
+    UtterancePlayed,
+    ActionsStarted,
+    ActionsFinished,
+    ObservationReady,
+    /// The user talked over the assistant, and what they said is on its way to the LLM
+    UserInterrupted,
+}
+
+/// Whose turn it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Turn {
+    /// Waiting for the user to say something
+    Listen,
+    /// Waiting for the LLM
+    Think,
+    /// Waiting for actions to finish
+    Act,
+    /// Waiting for the assistant to finish talking
+    Speak,
+}
+
+/// Kicks off the conversation.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Start {
+    /// Whether to send the initial prompt, ie. this isn't a resumed session
+    pub prompt: bool,
+}
+
+/// Whose turn it is right now.
+#[derive(Message)]
+#[rtype(result = "Turn")]
+pub struct GetTurn;
+
+/// Drives the turns of the conversation from the lifecycle events of the other actors:
+/// listen -> think -> act -> speak -> listen.
+pub struct Conversation {
+    llm: Recipient<ChatMessage>,
+    stt: Recipient<SttAction>,
+    interpreter: Recipient<GetObservations>,
+
+    turn: Turn,
+    /// STT is listening for barge-in, so there's no need to ask again
+    listening: bool,
+    thinking: bool,
+    interrupted: bool,
+    utterances: usize,
+    actions: usize,
+    observations: usize,
+}
+
+impl Actor for Conversation {
+    type Context = Context<Self>;
+}
+
+impl Conversation {
+    pub fn with(
+        llm: Recipient<ChatMessage>,
+        stt: Recipient<SttAction>,
+        interpreter: Recipient<GetObservations>,
+    ) -> Self {
+        Self {
+            llm,
+            stt,
+            interpreter,
+            turn: Turn::Listen,
+            listening: false,
+            thinking: false,
+            interrupted: false,
+            utterances: 0,
+            actions: 0,
+            observations: 0,
+        }
+    }
+
+    fn next_turn(&self) -> Turn {
+        if self.thinking || self.interrupted {
+            Turn::Think
+        } else if self.actions > 0 {
+            Turn::Act
+        } else if self.utterances > 0 {
+            Turn::Speak
+        } else if self.observations > 0 {
+            // The LLM gets to look at the observations before the user speaks
+            Turn::Think
+        } else {
+            Turn::Listen
+        }
+    }
+
+    fn advance(&mut self, ctx: &mut Context<Self>) {
+        let next = self.next_turn();
+        let observing = next == Turn::Think && !self.thinking && !self.interrupted;
+        if next == self.turn && !observing {
+            return;
+        }
+
+        println!("Conversation : {:?} -> {:?}", self.turn, next);
+        self.turn = next;
+
+        match next {
+            Turn::Listen => self.stt.do_send(SttAction::RecordUntilSilence),
+            Turn::Think if observing => {
+                // Mark as thinking straight away so this only happens once
+                self.thinking = true;
+                self.observations -= 1;
+
+                let llm = self.llm.clone();
+                self.interpreter
+                    .send(GetObservations)
+                    .into_actor(self)
+                    .map(move |observation, act, ctx| match observation {
+                        Ok(Some(observation)) => {
+                            println!("--- observation: {} ---", observation);
+                            llm.do_send(ChatMessage(observation, Role::User));
+                        }
+                        _ => {
+                            // Nothing there after all
+                            act.thinking = false;
+                            act.advance(ctx);
+                        }
+                    })
+                    .wait(ctx);
+            }
+            Turn::Speak | Turn::Think | Turn::Act => {}
+        }
+    }
+
+    /// Let the user talk over whatever's playing. Only once it's actually playing though,
+    /// as STT stops listening as soon as nothing is.
+    fn listen_for_barge_in(&mut self, ctx: &mut Context<Self>) {
+        if self.listening || self.turn == Turn::Listen {
+            return;
+        }
+
+        self.listening = true;
+        self.stt
+            .send(SttAction::ListenForBargeIn)
+            .into_actor(self)
+            .map(|_, act, _ctx| act.listening = false)
+            .spawn(ctx);
+    }
+}
+
+impl Handler<Start> for Conversation {
+    type Result = ();
+
+    fn handle(&mut self, msg: Start, ctx: &mut Context<Self>) -> Self::Result {
+        if msg.prompt {
+            self.thinking = true;
+            self.llm.do_send(ChatMessage(INITIAL_PROMPT.into(), Role::System));
+        }
+        self.turn = Turn::Think;
+        self.advance(ctx);
+    }
+}
+
+impl Handler<Lifecycle> for Conversation {
+    type Result = ();
+
+    fn handle(&mut self, msg: Lifecycle, ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            Lifecycle::LlmStarted => {
+                self.thinking = true;
+                self.interrupted = false;
+            }
+            Lifecycle::LlmFinished => self.thinking = false,
+            Lifecycle::UtteranceQueued => self.utterances += 1,
+            Lifecycle::PlaybackStarted => self.listen_for_barge_in(ctx),
+            Lifecycle::UtterancePlayed => self.utterances = self.utterances.saturating_sub(1),
+            Lifecycle::ActionsStarted => self.actions += 1,
+            Lifecycle::ActionsFinished => self.actions = self.actions.saturating_sub(1),
+            Lifecycle::ObservationReady => self.observations += 1,
+            Lifecycle::UserInterrupted => self.interrupted = true,
+        }
+        self.advance(ctx);
+    }
+}
+
+impl Handler<GetTurn> for Conversation {
+    type Result = MessageResult<GetTurn>;
+
+    fn handle(&mut self, _msg: GetTurn, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.turn)
+    }
+}
@@ -1,23 +1,68 @@
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, time::UNIX_EPOCH};
 
+use anyhow::{Context, Result};
 use globwalk::GlobWalkerBuilder;
+use sha2::{Digest, Sha256};
 use std::path::Path;
 
+use crate::loader::{loader_for, Location};
+
+/// Files picked up when the user points us at a directory
+pub const DOCUMENT_PATTERN: &str = "**/*.{md,markdown,txt,html,htm,pdf,docx}";
+
+/// Hex SHA-256 of some text, to tell whether it has changed.
+pub fn content_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// When the file was last modified, in ms since the epoch.
+/// Cheap enough to check before reading a file to see if it changed.
+pub fn modified_ms(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+/// Where a document came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub source: PathBuf,
+    pub title: Option<String>,
+    /// Page or section within the source, if it was split up
+    pub location: Option<Location>,
+    pub modified_ms: Option<u64>,
+    /// Of the whole source file, so a changed file can be spotted
+    pub file_hash: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct Document {
     id: String,
     text: String,
+    metadata: Metadata,
 }
 
 pub struct DocumentCollection(Vec<Document>);
 
 impl Document {
     pub fn new(id: String, text: String) -> Self {
-        Self { id, text }
+        let metadata = Metadata {
+            source: PathBuf::from(&id),
+            title: None,
+            location: None,
+            modified_ms: None,
+            file_hash: content_hash(&text),
+        };
+        Self { id, text, metadata }
     }
     pub fn id(&self) -> &str {
         &self.id
     }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
 }
 
 impl AsRef<str> for Document {
@@ -31,6 +76,11 @@ pub struct DirectoryLoader {
 }
 
 impl DirectoryLoader {
+    /// Loads exactly these files.
+    pub fn with_paths(paths: Vec<PathBuf>) -> Self {
+        Self { paths }
+    }
+
     pub fn find_files_with_pattern(path: &Path, pattern: &str) -> Self {
         let walker = GlobWalkerBuilder::new(path, pattern)
             .build()
@@ -50,23 +100,58 @@ impl DirectoryLoader {
         Self { paths }
     }
 
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Loads every file, skipping the ones that can't be read.
     pub fn load(&self) -> Vec<Document> {
         self.paths
             .iter()
-            .filter_map(|path| {
-                if let Some(file_str) = path.to_str() {
-                    let file_path = file_str.to_string();
-                    let content = fs::read_to_string(&file_path)
-                        .map_err(|e| format!("Failed to read file {}: {}", file_path, e))
-                        .unwrap();
-                    Some(Document {
-                        id: file_path,
-                        text: content,
-                    })
-                } else {
-                    None
+            .flat_map(|path| match load_file(path) {
+                Ok(documents) => documents,
+                Err(e) => {
+                    eprintln!("Loader       : Skipping {}: {}", path.display(), e);
+                    vec![]
                 }
             })
             .collect()
     }
 }
+
+/// Loads a file with the loader that suits it, one document per part.
+pub fn load_file(path: &Path) -> Result<Vec<Document>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read file {}", path.display()))?;
+    let parts = loader_for(path, &bytes)?.load(&bytes)?;
+
+    let file_hash = format!("{:x}", Sha256::digest(&bytes));
+    let modified_ms = modified_ms(path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned());
+    let single = parts.len() == 1;
+
+    Ok(parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| {
+            // Parts of the same file need ids of their own
+            let id = if single {
+                path.display().to_string()
+            } else {
+                format!("{}#{}", path.display(), i)
+            };
+            Document {
+                id,
+                text: part.text,
+                metadata: Metadata {
+                    source: path.to_path_buf(),
+                    title: part.title.or_else(|| stem.clone()),
+                    location: part.location,
+                    modified_ms,
+                    file_hash: file_hash.clone(),
+                },
+            }
+        })
+        .collect())
+}
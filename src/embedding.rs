@@ -1,8 +1,249 @@
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
 use actix::prelude::*;
-use rust_bert::pipelines::sentence_embeddings::{SentenceEmbeddingsModel, SentenceEmbeddingsBuilder, SentenceEmbeddingsModelType::AllMiniLmL6V2, Embedding};
+use anyhow::{anyhow, bail, ensure, Result};
+use rust_bert::pipelines::sentence_embeddings::{
+    Embedding, SentenceEmbeddingsBuilder, SentenceEmbeddingsModel, SentenceEmbeddingsModelType,
+};
+
+/// How many texts go through the model at once, unless set with `batch_size`
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Where offline mode looks for models named by type, eg. `models/all-MiniLM-L6-v2`
+pub const MODELS_DIR: &str = "models";
+
+/// Every model rust-bert can download, by its name on Hugging Face under `sentence-transformers/`.
+pub const MODEL_TYPES: &[(&str, SentenceEmbeddingsModelType)] = &[
+    ("distiluse-base-multilingual-cased", SentenceEmbeddingsModelType::DistiluseBaseMultilingualCased),
+    ("bert-base-nli-mean-tokens", SentenceEmbeddingsModelType::BertBaseNliMeanTokens),
+    ("all-MiniLM-L12-v2", SentenceEmbeddingsModelType::AllMiniLmL12V2),
+    ("all-MiniLM-L6-v2", SentenceEmbeddingsModelType::AllMiniLmL6V2),
+    ("all-distilroberta-v1", SentenceEmbeddingsModelType::AllDistilrobertaV1),
+    ("paraphrase-albert-small-v2", SentenceEmbeddingsModelType::ParaphraseAlbertSmallV2),
+    ("sentence-t5-base", SentenceEmbeddingsModelType::SentenceT5Base),
+];
+
+/// Files every converted sentence-transformers directory needs. The module and
+/// tokenizer files it also needs depend on what these say.
+const REQUIRED_FILES: &[&str] = &[
+    "modules.json",
+    "config.json",
+    "sentence_bert_config.json",
+    "rust_model.ot",
+];
+
+/// Which model to embed with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelSource {
+    /// Downloaded on first use and cached by rust-bert
+    Remote(SentenceEmbeddingsModelType),
+    /// A sentence-transformers model converted for rust-bert, with its weights in `rust_model.ot`
+    Local(PathBuf),
+}
+
+impl Default for ModelSource {
+    fn default() -> Self {
+        ModelSource::Remote(SentenceEmbeddingsModelType::AllMiniLmL6V2)
+    }
+}
+
+impl FromStr for ModelSource {
+    type Err = anyhow::Error;
+
+    /// A model name like "all-MiniLM-L6-v2" (any case), or else a path to a local model.
+    fn from_str(name: &str) -> Result<Self> {
+        if let Some((_, model_type)) = MODEL_TYPES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        {
+            return Ok(ModelSource::Remote(*model_type));
+        }
+        let path = PathBuf::from(name);
+        if path.is_dir() {
+            return Ok(ModelSource::Local(path));
+        }
+        let known: Vec<&str> = MODEL_TYPES.iter().map(|(known, _)| *known).collect();
+        bail!(
+            "{:?} is neither a model directory nor one of {}",
+            name,
+            known.join(", ")
+        )
+    }
+}
+
+impl fmt::Display for ModelSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelSource::Remote(model_type) => write!(f, "{}", model_name(*model_type)),
+            ModelSource::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+fn model_name(model_type: SentenceEmbeddingsModelType) -> &'static str {
+    MODEL_TYPES
+        .iter()
+        .find(|(_, known)| *known == model_type)
+        .map_or("unknown model", |(name, _)| *name)
+}
+
+/// How to load the embedding model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingConfig {
+    pub model: ModelSource,
+    /// Never download. Models named by type are loaded from `models_dir` instead.
+    pub offline: bool,
+    pub models_dir: PathBuf,
+    pub batch_size: usize,
+    /// Threads torch uses inside each model. Torch picks, usually one per core, when
+    /// unset, so set it when a pool has several models or they'll fight over the cores.
+    pub torch_threads: Option<usize>,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            model: ModelSource::default(),
+            offline: false,
+            models_dir: PathBuf::from(MODELS_DIR),
+            batch_size: DEFAULT_BATCH_SIZE,
+            torch_threads: None,
+        }
+    }
+}
+
+impl EmbeddingConfig {
+    /// Reads `EMBEDDING_MODEL` (a name or a directory), `EMBEDDING_OFFLINE=1`,
+    /// `EMBEDDING_MODELS_DIR`, `EMBEDDING_BATCH_SIZE` and `EMBEDDING_TORCH_THREADS`,
+    /// falling back to the defaults.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        if let Ok(model) = env::var("EMBEDDING_MODEL") {
+            config.model = model.parse()?;
+        }
+        if let Ok(offline) = env::var("EMBEDDING_OFFLINE") {
+            config.offline = matches!(offline.as_str(), "1" | "true" | "yes");
+        }
+        if let Ok(models_dir) = env::var("EMBEDDING_MODELS_DIR") {
+            config.models_dir = PathBuf::from(models_dir);
+        }
+        if let Ok(batch_size) = env::var("EMBEDDING_BATCH_SIZE") {
+            config.batch_size = batch_size
+                .parse()
+                .map_err(|_| anyhow!("EMBEDDING_BATCH_SIZE must be a number, not {:?}", batch_size))?;
+        }
+        if let Ok(torch_threads) = env::var("EMBEDDING_TORCH_THREADS") {
+            config.torch_threads = Some(torch_threads.parse().map_err(|_| {
+                anyhow!("EMBEDDING_TORCH_THREADS must be a number, not {:?}", torch_threads)
+            })?);
+        }
+        Ok(config)
+    }
+
+    /// The directory to load from, if the model isn't to be downloaded.
+    pub fn local_dir(&self) -> Option<PathBuf> {
+        match &self.model {
+            ModelSource::Local(path) => Some(path.clone()),
+            ModelSource::Remote(model_type) if self.offline => {
+                Some(self.models_dir.join(model_name(*model_type)))
+            }
+            ModelSource::Remote(_) => None,
+        }
+    }
+
+    /// Fails with what's missing if the model can't be loaded without downloading.
+    pub fn check(&self) -> Result<()> {
+        let Some(dir) = self.local_dir() else {
+            return Ok(());
+        };
+        let missing = missing_files(&dir)?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "Embedding model {} is missing {} in {}.",
+            self.model,
+            missing.join(", "),
+            dir.display()
+        );
+        if let ModelSource::Remote(model_type) = &self.model {
+            message.push_str(&format!(
+                " Offline mode doesn't download, so get sentence-transformers/{} from Hugging Face \
+                 and convert it with rust-bert's convert_model.py, or turn offline mode off.",
+                model_name(*model_type)
+            ));
+        }
+        bail!(message)
+    }
+}
+
+fn missing_files(dir: &Path) -> Result<Vec<String>> {
+    let mut required: Vec<String> = REQUIRED_FILES.iter().map(|file| file.to_string()).collect();
+    required.extend(module_files(dir)?);
+    required.extend(tokenizer_files(dir)?);
+    Ok(required.into_iter().filter(|file| !dir.join(file).is_file()).collect())
+}
+
+fn read_json(path: &Path) -> Result<Option<serde_json::Value>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let value = serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+    Ok(Some(value))
+}
+
+/// The pooling module's config, and a dense module's config and weights if there is one,
+/// in the directories modules.json puts them in.
+fn module_files(dir: &Path) -> Result<Vec<String>> {
+    let Some(modules) = read_json(&dir.join("modules.json"))? else {
+        return Ok(vec![]);
+    };
+    let modules = modules
+        .as_array()
+        .ok_or_else(|| anyhow!("modules.json in {} isn't a list", dir.display()))?;
+
+    let mut files = vec![];
+    for module in modules {
+        let kind = module["type"].as_str().unwrap_or_default();
+        let path = module["path"].as_str().unwrap_or_default();
+        if kind.ends_with(".Pooling") {
+            files.push(format!("{}/config.json", path));
+        } else if kind.ends_with(".Dense") {
+            files.push(format!("{}/config.json", path));
+            files.push(format!("{}/rust_model.ot", path));
+        }
+    }
+    Ok(files)
+}
+
+/// The vocabulary the tokenizer for config.json's `model_type` is loaded from.
+fn tokenizer_files(dir: &Path) -> Result<Vec<String>> {
+    let Some(config) = read_json(&dir.join("config.json"))? else {
+        return Ok(vec![]);
+    };
+    let files: &[&str] = match config["model_type"].as_str().unwrap_or_default() {
+        "bert" | "distilbert" => &["vocab.txt"],
+        "roberta" => &["vocab.json", "merges.txt"],
+        "albert" | "t5" => &["spiece.model"],
+        model_type => bail!(
+            "Embedding model in {} has model_type {:?}, which sentence embeddings can't use",
+            dir.display(),
+            model_type
+        ),
+    };
+    Ok(files.iter().map(|file| file.to_string()).collect())
+}
 
 #[derive(Message)]
-#[rtype(result = "Embedding")]
+#[rtype(result = "Result<Embedding>")]
 pub struct EmbeddingQuery(pub String);
 
 impl AsRef<str> for EmbeddingQuery {
@@ -11,18 +252,65 @@ impl AsRef<str> for EmbeddingQuery {
     }
 }
 
+/// Embeds several texts in one go, which is a lot quicker than one at a time.
+/// They're fed to the model `batch_size` at a time, so any number can be sent.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<Embedding>>")]
+pub struct EmbedBatch(pub Vec<String>);
+
+/// How much embedding the models have done between them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EmbeddingStats {
+    pub texts: usize,
+    pub batches: usize,
+    /// Time spent in the model, added up across threads
+    pub busy: Duration,
+}
+
+impl EmbeddingStats {
+    pub fn texts_per_second(&self) -> f64 {
+        if self.busy.is_zero() {
+            0.0
+        } else {
+            self.texts as f64 / self.busy.as_secs_f64()
+        }
+    }
+}
+
+impl fmt::Display for EmbeddingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} texts in {} batches, {:.1}s in the model ({:.0} texts/s)",
+            self.texts,
+            self.batches,
+            self.busy.as_secs_f64(),
+            self.texts_per_second()
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "EmbeddingStats")]
+pub struct GetStats;
+
+/// Length of the vectors the model makes, for creating collections to hold them.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct GetDimension;
+
 pub struct EmbeddingModel {
-    model: SentenceEmbeddingsModel,
+    /// Only missing in a pool thread that couldn't load it, which `pool` reports
+    model: Option<SentenceEmbeddingsModel>,
+    dimension: usize,
+    batch_size: usize,
+    /// Shared by every model in a pool
+    stats: Arc<Mutex<EmbeddingStats>>,
 }
 
 impl Default for EmbeddingModel {
     fn default() -> Self {
-        println!("Embedding    : Creating model");
-        let model = SentenceEmbeddingsBuilder::remote(AllMiniLmL6V2)
-            .create_model()
-            .expect("Cannot create model");
-        println!("Embedding    : Model created");
-        Self { model }
+        Self::new(EmbeddingConfig::default()).expect("Cannot create model")
     }
 }
 
@@ -30,12 +318,172 @@ impl Actor for EmbeddingModel {
     type Context = SyncContext<Self>;
 }
 
+impl EmbeddingModel {
+    pub fn new(config: EmbeddingConfig) -> Result<Self> {
+        config.check()?;
+        // Applies to the thread the model runs on, which in a pool is its own
+        if let Some(torch_threads) = config.torch_threads {
+            tch::set_num_threads(torch_threads.max(1) as i32);
+        }
+
+        println!("Embedding    : Creating model {}", config.model);
+        let model = match (config.local_dir(), &config.model) {
+            (Some(dir), _) => SentenceEmbeddingsBuilder::local(dir).create_model()?,
+            (None, ModelSource::Remote(model_type)) => {
+                SentenceEmbeddingsBuilder::remote(*model_type).create_model()?
+            }
+            (None, ModelSource::Local(_)) => unreachable!("Local models always have a directory"),
+        };
+
+        // Models don't say how long their vectors are, so measure one
+        let dimension = model
+            .encode(&["dimension"])?
+            .pop()
+            .map(|vector| vector.len())
+            .ok_or_else(|| anyhow!("Model {} returned no embedding", config.model))?;
+        println!("Embedding    : Model created, with dimension {}", dimension);
+
+        Ok(Self {
+            model: Some(model),
+            dimension,
+            batch_size: config.batch_size.max(1),
+            stats: Arc::default(),
+        })
+    }
+
+    /// Starts a model on each of `threads` threads, sharing one mailbox and one set of stats.
+    /// Waits for every thread to load its model, so a model that can't be loaded as
+    /// configured is an error rather than a panic on every thread.
+    ///
+    /// Senders waiting on `send` are held back once the mailbox is full, so keep only
+    /// a few batches in flight rather than sending thousands at once.
+    pub fn pool(threads: usize, config: EmbeddingConfig) -> Result<Addr<Self>> {
+        // Missing files are one clear error, rather than one from every thread
+        config.check()?;
+
+        // Models can't be moved between threads, so each thread loads its own and
+        // reports back how that went
+        let threads = threads.max(1);
+        let (loaded, results) = mpsc::channel();
+        let stats: Arc<Mutex<EmbeddingStats>> = Arc::default();
+        let pool = SyncArbiter::start(threads, move || {
+            let model = match Self::new(config.clone()) {
+                Ok(model) => {
+                    let _ = loaded.send(Ok(()));
+                    model
+                }
+                // Never used, as the pool is dropped when any thread fails
+                Err(e) => {
+                    let _ = loaded.send(Err(e));
+                    Self {
+                        model: None,
+                        dimension: 0,
+                        batch_size: 1,
+                        stats: Arc::default(),
+                    }
+                }
+            };
+            Self {
+                stats: stats.clone(),
+                ..model
+            }
+        });
+
+        for _ in 0..threads {
+            results
+                .recv()
+                .map_err(|_| anyhow!("Embedding model thread stopped while loading"))??;
+        }
+        Ok(pool)
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn encode(&self, texts: &[String]) -> Result<Vec<Embedding>> {
+        let model = self
+            .model
+            .as_ref()
+            .ok_or_else(|| anyhow!("Embedding model isn't loaded"))?;
+        encode_in_batches(texts, self.batch_size, &self.stats, |batch| Ok(model.encode(batch)?))
+    }
+}
+
+/// Feeds `texts` to `encode` `batch_size` at a time, and adds each batch to `stats`.
+/// Fails if a batch fails or comes back with the wrong number of embeddings.
+pub fn encode_in_batches(
+    texts: &[String],
+    batch_size: usize,
+    stats: &Mutex<EmbeddingStats>,
+    mut encode: impl FnMut(&[String]) -> Result<Vec<Embedding>>,
+) -> Result<Vec<Embedding>> {
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(batch_size.max(1)) {
+        let started = Instant::now();
+        let mut vectors = encode(batch)?;
+        let elapsed = started.elapsed();
+        ensure!(
+            vectors.len() == batch.len(),
+            "Model returned {} embeddings for {} texts",
+            vectors.len(),
+            batch.len()
+        );
+        embeddings.append(&mut vectors);
+
+        let mut stats = stats.lock().unwrap();
+        stats.texts += batch.len();
+        stats.batches += 1;
+        stats.busy += elapsed;
+        println!(
+            "Embedding    : Embedded {} texts in {}ms ({:.0} texts/s)",
+            batch.len(),
+            elapsed.as_millis(),
+            batch.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+    Ok(embeddings)
+}
+
 impl Handler<EmbeddingQuery> for EmbeddingModel {
-    type Result = Embedding;
+    type Result = Result<Embedding>;
 
     fn handle(&mut self, embedding_query: EmbeddingQuery, _ctx: &mut SyncContext<Self>) -> Self::Result {
         println!("Embedding    : Received embedding query");
 
-        self.model.encode(&[embedding_query]).expect("Cannot embed query").pop().expect("Empty result")
+        self.encode(&[embedding_query.0])?
+            .pop()
+            .ok_or_else(|| anyhow!("Model returned no embedding"))
+    }
+}
+
+impl Handler<EmbedBatch> for EmbeddingModel {
+    type Result = Result<Vec<Embedding>>;
+
+    fn handle(&mut self, batch: EmbedBatch, _ctx: &mut SyncContext<Self>) -> Self::Result {
+        println!("Embedding    : Received batch of {}", batch.0.len());
+
+        self.encode(&batch.0)
+    }
+}
+
+impl Handler<GetDimension> for EmbeddingModel {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: GetDimension, _ctx: &mut SyncContext<Self>) -> Self::Result {
+        self.dimension
+    }
+}
+
+impl Handler<GetStats> for EmbeddingModel {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _msg: GetStats, _ctx: &mut SyncContext<Self>) -> Self::Result {
+        MessageResult(*self.stats.lock().unwrap())
     }
 }
@@ -0,0 +1,215 @@
+use std::fmt;
+
+const FENCE_LEN: usize = 3;
+const MAX_INFO_LEN: usize = 32;
+
+/// Something the parser recognised in the stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// Text outside of any fenced block
+    Text(String),
+    /// A complete fenced block, eg. ```speech ... ```
+    Block { lang: String, content: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The info string after an opening fence was too long or had a backtick in it
+    InvalidInfo(String),
+    /// The stream ended while a block was still open
+    Unterminated { lang: String, content: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidInfo(info) => write!(f, "invalid fence info string {:?}", info),
+            ParseError::Unterminated { lang, content } => {
+                write!(f, "unterminated {:?} block ({} chars)", lang, content.len())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Segment(Segment),
+    Error(ParseError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    /// Outside a block, counting a run of backticks (possibly zero)
+    Text { ticks: usize },
+    /// Reading the language after an opening fence
+    Info { ticks: usize, lang: String },
+    /// Inside a block
+    Block(BlockState),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BlockState {
+    ticks: usize,
+    lang: String,
+    content: String,
+    /// Run of backticks that may turn out to be the closing fence
+    close_run: usize,
+    /// Whether we're inside a JSON string, where backticks are just content
+    in_string: bool,
+    escaped: bool,
+}
+
+/// Incremental parser for fenced blocks that works on characters,
+/// so fences can be split across any number of tokens.
+#[derive(Debug)]
+pub struct FenceParser {
+    state: State,
+    text: String,
+}
+
+impl Default for FenceParser {
+    fn default() -> Self {
+        Self {
+            state: State::Text { ticks: 0 },
+            text: String::new(),
+        }
+    }
+}
+
+impl FenceParser {
+    /// Feed the next token and return whatever got completed by it.
+    pub fn push(&mut self, token: &str) -> Vec<Event> {
+        let mut events = vec![];
+        for c in token.chars() {
+            self.push_char(c, &mut events);
+        }
+        events
+    }
+
+    /// Call at the end of a stream. Anything left over is emitted as text,
+    /// or reported as an error if a block was never closed.
+    pub fn finish(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+        match std::mem::replace(&mut self.state, State::Text { ticks: 0 }) {
+            State::Text { ticks } => {
+                self.text.extend(std::iter::repeat('`').take(ticks));
+            }
+            State::Info { lang, .. } => {
+                events.push(Event::Error(ParseError::Unterminated {
+                    lang,
+                    content: String::new(),
+                }));
+            }
+            State::Block(block) => {
+                let mut content = block.content;
+                content.extend(std::iter::repeat('`').take(block.close_run));
+                events.push(Event::Error(ParseError::Unterminated {
+                    lang: block.lang,
+                    content,
+                }));
+            }
+        }
+        self.flush_text(&mut events);
+        events
+    }
+
+    /// The language and content so far of the block currently being parsed, if any.
+    /// Backticks that might still turn out to be the closing fence are left out.
+    pub fn open_block(&self) -> Option<(&str, &str)> {
+        match &self.state {
+            State::Block(block) => Some((&block.lang, &block.content)),
+            _ => None,
+        }
+    }
+
+    /// True if nothing is buffered, ie. we're not halfway through anything.
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Text { ticks: 0 } && self.text.is_empty()
+    }
+
+    fn push_char(&mut self, c: char, events: &mut Vec<Event>) {
+        match &mut self.state {
+            State::Text { ticks } => {
+                if c == '`' {
+                    *ticks += 1;
+                } else if *ticks >= FENCE_LEN {
+                    let ticks = *ticks;
+                    self.flush_text(events);
+                    self.state = State::Info {
+                        ticks,
+                        lang: String::new(),
+                    };
+                    self.push_char(c, events);
+                } else {
+                    // Inline backticks are just text
+                    let ticks = *ticks;
+                    self.text.extend(std::iter::repeat('`').take(ticks));
+                    self.text.push(c);
+                    self.state = State::Text { ticks: 0 };
+                }
+            }
+            State::Info { ticks, lang } => {
+                if c.is_whitespace() {
+                    let block = BlockState {
+                        ticks: *ticks,
+                        lang: std::mem::take(lang),
+                        content: String::new(),
+                        close_run: 0,
+                        in_string: false,
+                        escaped: false,
+                    };
+                    self.state = State::Block(block);
+                } else if c == '`' || lang.len() >= MAX_INFO_LEN {
+                    // Not a fence we understand. Report it and carry on as text.
+                    let mut raw: String = std::iter::repeat('`').take(*ticks).collect();
+                    raw.push_str(lang);
+                    raw.push(c);
+                    events.push(Event::Error(ParseError::InvalidInfo(raw.clone())));
+                    self.text.push_str(&raw);
+                    self.state = State::Text { ticks: 0 };
+                } else {
+                    lang.push(c);
+                }
+            }
+            State::Block(block) => {
+                if c == '`' && !block.in_string {
+                    block.close_run += 1;
+                    if block.close_run >= block.ticks {
+                        let lang = std::mem::take(&mut block.lang);
+                        let content = std::mem::take(&mut block.content);
+                        events.push(Event::Segment(Segment::Block { lang, content }));
+                        self.state = State::Text { ticks: 0 };
+                    }
+                    return;
+                }
+
+                // The backticks were part of the content after all
+                block
+                    .content
+                    .extend(std::iter::repeat('`').take(block.close_run));
+                block.close_run = 0;
+                block.content.push(c);
+
+                // Track JSON strings so that backticks inside them don't close the block
+                if block.lang == "json" {
+                    if block.escaped {
+                        block.escaped = false;
+                    } else if block.in_string && c == '\\' {
+                        block.escaped = true;
+                    } else if c == '"' {
+                        block.in_string = !block.in_string;
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush_text(&mut self, events: &mut Vec<Event>) {
+        let text = std::mem::take(&mut self.text);
+        if !text.trim().is_empty() {
+            events.push(Event::Segment(Segment::Text(text)));
+        }
+    }
+}
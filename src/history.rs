@@ -0,0 +1,136 @@
+use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs, Role};
+use tiktoken_rs::CoreBPE;
+
+/// Leaves room for the reply in an 8k context.
+pub const DEFAULT_BUDGET: usize = 6_000;
+
+/// Name given to the message holding a summary of older turns.
+const SUMMARY_NAME: &str = "summary";
+
+/// Tokens each message costs on top of its content.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// What to do with the oldest turns once the history is over budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Drop them
+    Truncate,
+    /// Ask the LLM to summarise them into a single message
+    Summarise,
+}
+
+/// The conversation so far, kept within a token budget.
+/// System messages (eg. `INITIAL_PROMPT`) are never dropped.
+pub struct History {
+    messages: Vec<ChatCompletionRequestMessage>,
+    budget: usize,
+    overflow: Overflow,
+    bpe: CoreBPE,
+}
+
+impl History {
+    pub fn new(budget: usize, overflow: Overflow) -> Self {
+        Self {
+            messages: vec![],
+            budget,
+            overflow,
+            bpe: tiktoken_rs::cl100k_base().expect("Cannot load tokenizer"),
+        }
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    pub fn messages(&self) -> &[ChatCompletionRequestMessage] {
+        &self.messages
+    }
+
+    pub fn push(&mut self, message: ChatCompletionRequestMessage) {
+        self.messages.push(message);
+    }
+
+    pub fn count_tokens(&self, message: &ChatCompletionRequestMessage) -> usize {
+        let content = message.content.as_deref().unwrap_or_default();
+        let arguments = message
+            .function_call
+            .as_ref()
+            .map(|call| call.arguments.as_str())
+            .unwrap_or_default();
+
+        TOKENS_PER_MESSAGE
+            + self.bpe.encode_with_special_tokens(content).len()
+            + self.bpe.encode_with_special_tokens(arguments).len()
+    }
+
+    pub fn tokens(&self) -> usize {
+        self.messages.iter().map(|message| self.count_tokens(message)).sum()
+    }
+
+    /// Removes the oldest turns until the history fits the budget, and returns them.
+    /// The latest message is always kept, even if it's over budget on its own.
+    /// An earlier summary is removed too, so it gets folded into the next one.
+    pub fn trim(&mut self) -> Vec<ChatCompletionRequestMessage> {
+        let mut removed = vec![];
+        let mut tokens = self.tokens();
+
+        while tokens > self.budget {
+            let oldest = self.messages[..self.messages.len().saturating_sub(1)]
+                .iter()
+                .position(|message| !is_pinned(message));
+            let Some(oldest) = oldest else {
+                break;
+            };
+
+            let message = self.messages.remove(oldest);
+            tokens -= self.count_tokens(&message);
+            removed.push(message);
+        }
+
+        removed
+    }
+
+    /// Puts a summary of older turns right after the system messages.
+    pub fn insert_summary(&mut self, summary: ChatCompletionRequestMessage) {
+        insert_summary(&mut self.messages, summary);
+    }
+}
+
+pub fn summary_message(summary: &str) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessageArgs::default()
+        .role(Role::System)
+        .name(SUMMARY_NAME)
+        .content(format!("Summary of the conversation so far: {}", summary))
+        .build()
+        .unwrap()
+}
+
+pub fn insert_summary(
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    summary: ChatCompletionRequestMessage,
+) {
+    let position = messages
+        .iter()
+        .position(|message| !is_pinned(message))
+        .unwrap_or(messages.len());
+    messages.insert(position, summary);
+}
+
+/// Renders messages as a plain transcript, for the summariser to read.
+pub fn transcript(messages: &[ChatCompletionRequestMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            format!(
+                "{:?}: {}",
+                message.role,
+                message.content.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn is_pinned(message: &ChatCompletionRequestMessage) -> bool {
+    matches!(message.role, Role::System) && message.name.as_deref() != Some(SUMMARY_NAME)
+}
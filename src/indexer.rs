@@ -0,0 +1,543 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use actix::{dev::ToEnvelope, prelude::*};
+use anyhow::{anyhow, bail, Result};
+use futures::{stream, StreamExt};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chunker::{Chunk, Chunker},
+    document_loader::{load_file, modified_ms, Document},
+    embedding::{EmbedBatch, GetDimension, GetStats},
+    keyword_index::{AddChunks, ChunkCount, Flush, KeywordChunk, KeywordIndex, RemoveMatching},
+    vector_store::{
+        self, CollectionInfo, Condition, DeleteMatching, Distance, EnsureCollection, Filter, Payload, Scroll,
+        Upsert, VectorDb, TEXT_FIELD,
+    },
+};
+
+/// How many chunks are embedded and upserted at a time, unless set with `batch_size`
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// How many batches are waiting on the embedding model at once, unless set with `in_flight`
+pub const DEFAULT_IN_FLIGHT: usize = 2;
+
+/// Payload fields stored alongside `TEXT_FIELD`
+pub const PATH_FIELD: &str = "path";
+pub const DOCUMENT_FIELD: &str = "document";
+pub const CHUNK_INDEX_FIELD: &str = "chunk_index";
+/// Byte offsets of the chunk in its document
+pub const START_FIELD: &str = "start";
+pub const END_FIELD: &str = "end";
+pub const HASH_FIELD: &str = "hash";
+pub const MODIFIED_FIELD: &str = "modified_ms";
+pub const TITLE_FIELD: &str = "title";
+pub const LOCATION_FIELD: &str = "location";
+
+/// Every payload field a point can have
+const POINT_FIELDS: &[&str] = &[
+    PATH_FIELD,
+    DOCUMENT_FIELD,
+    CHUNK_INDEX_FIELD,
+    START_FIELD,
+    END_FIELD,
+    TEXT_FIELD,
+    HASH_FIELD,
+    MODIFIED_FIELD,
+    TITLE_FIELD,
+    LOCATION_FIELD,
+];
+
+/// Bring a collection up to date with these files: new and changed files are embedded
+/// and upserted, and the points of changed or deleted files are removed.
+#[derive(Message)]
+#[rtype(result = "Result<IndexReport>")]
+pub struct Index {
+    pub paths: Vec<PathBuf>,
+    pub collection_name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IndexReport {
+    pub collection_name: String,
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// How many chunks were embedded for the added and updated files
+    pub chunks: usize,
+}
+
+impl fmt::Display for IndexReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Indexed {}: {} added, {} updated, {} removed, {} unchanged ({} chunks embedded).",
+            self.collection_name, self.added, self.updated, self.removed, self.unchanged, self.chunks
+        )
+    }
+}
+
+/// What the collection knows about a file it has points for.
+struct IndexedFile {
+    hash: String,
+    modified_ms: Option<u64>,
+}
+
+/// A chunk of a document and what gets stored alongside it.
+struct Point {
+    chunk: Chunk,
+    path: String,
+    /// Position of the chunk in its document
+    index: usize,
+    title: Option<String>,
+    location: Option<String>,
+    /// Of the whole file, so a changed file can be spotted
+    hash: String,
+    modified_ms: Option<u64>,
+}
+
+impl Point {
+    fn new(document: &Document, index: usize, chunk: Chunk) -> Self {
+        let metadata = document.metadata();
+        Self {
+            chunk,
+            path: metadata.source.display().to_string(),
+            index,
+            title: metadata.title.clone(),
+            location: metadata.location.as_ref().map(|location| location.to_string()),
+            hash: metadata.file_hash.clone(),
+            modified_ms: metadata.modified_ms,
+        }
+    }
+
+    fn id(&self) -> u64 {
+        point_id(&self.chunk.parent, self.index)
+    }
+
+    fn payload(&self) -> Payload {
+        let mut payload: Payload = HashMap::from([
+            (PATH_FIELD.to_string(), self.path.clone().into()),
+            (DOCUMENT_FIELD.to_string(), self.chunk.parent.clone().into()),
+            (CHUNK_INDEX_FIELD.to_string(), self.index.into()),
+            (START_FIELD.to_string(), self.chunk.start.into()),
+            (END_FIELD.to_string(), self.chunk.end.into()),
+            (TEXT_FIELD.to_string(), self.chunk.text.clone().into()),
+            (HASH_FIELD.to_string(), self.hash.clone().into()),
+        ]);
+        if let Some(modified_ms) = self.modified_ms {
+            payload.insert(MODIFIED_FIELD.to_string(), modified_ms.into());
+        }
+        if let Some(title) = &self.title {
+            payload.insert(TITLE_FIELD.to_string(), title.clone().into());
+        }
+        if let Some(location) = &self.location {
+            payload.insert(LOCATION_FIELD.to_string(), location.clone().into());
+        }
+        payload
+    }
+
+    /// The same chunk for the keyword index, under the same id.
+    fn keyword_chunk(&self) -> KeywordChunk {
+        let mut payload = self.payload();
+        payload.remove(TEXT_FIELD);
+        KeywordChunk {
+            id: self.id(),
+            text: self.chunk.text.clone(),
+            payload,
+        }
+    }
+}
+
+/// What the indexer needs from the embedding model.
+#[derive(Clone)]
+struct Embedding {
+    batch: Recipient<EmbedBatch>,
+    dimension: Recipient<GetDimension>,
+    stats: Recipient<GetStats>,
+}
+
+/// Turns documents on disk into points in a vector store collection.
+pub struct Indexer {
+    embedding: Embedding,
+    store: Addr<VectorDb>,
+    /// Kept in step with the collection, if set
+    keywords: Option<Addr<KeywordIndex>>,
+    chunker: Chunker,
+    batch_size: usize,
+    in_flight: usize,
+}
+
+impl Actor for Indexer {
+    type Context = Context<Self>;
+}
+
+impl Indexer {
+    /// The embedding is usually an `EmbeddingModel`.
+    pub fn with<E>(embedding: Addr<E>, store: Addr<VectorDb>) -> Self
+    where
+        E: Actor + Handler<EmbedBatch> + Handler<GetDimension> + Handler<GetStats>,
+        E::Context: ToEnvelope<E, EmbedBatch> + ToEnvelope<E, GetDimension> + ToEnvelope<E, GetStats>,
+    {
+        Self {
+            embedding: Embedding {
+                batch: embedding.clone().recipient(),
+                dimension: embedding.clone().recipient(),
+                stats: embedding.recipient(),
+            },
+            store,
+            keywords: None,
+            chunker: Chunker::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            in_flight: DEFAULT_IN_FLIGHT,
+        }
+    }
+
+    pub fn chunker(mut self, chunker: Chunker) -> Self {
+        self.chunker = chunker;
+        self
+    }
+
+    /// Index every chunk for keyword search too.
+    pub fn keywords(mut self, keywords: Addr<KeywordIndex>) -> Self {
+        self.keywords = Some(keywords);
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Keep up to this many batches with the embedding model, eg. one per thread in its pool.
+    /// No more are sent until one comes back, so big folders can't flood its mailbox.
+    pub fn in_flight(mut self, in_flight: usize) -> Self {
+        self.in_flight = in_flight.max(1);
+        self
+    }
+}
+
+impl Handler<Index> for Indexer {
+    type Result = ResponseFuture<Result<IndexReport>>;
+
+    fn handle(&mut self, msg: Index, _ctx: &mut Self::Context) -> Self::Result {
+        println!("Indexer      : Indexing {} files into {}", msg.paths.len(), msg.collection_name);
+
+        let embedding = self.embedding.clone();
+        let store = self.store.clone();
+        let keywords = self.keywords.clone();
+        let chunker = self.chunker.clone();
+        let batch_size = self.batch_size;
+        let in_flight = self.in_flight;
+
+        Box::pin(async move {
+            let result: Result<IndexReport> = async {
+                let collection_name = msg.collection_name;
+                let mut indexed = indexed_files(&store, &collection_name).await?;
+                if let Some(keywords) = &keywords {
+                    // Keyword search is a bonus, so indexing goes on without it
+                    if !indexed.is_empty() {
+                        if let Err(e) = backfill_keywords(&store, keywords, &collection_name).await {
+                            eprintln!("Indexer      : Unable to fill the keyword index: {}", e);
+                        }
+                    }
+                }
+                let mut report = IndexReport {
+                    collection_name: collection_name.clone(),
+                    ..Default::default()
+                };
+
+                // Only read the files whose modification time changed, and only
+                // re-embed the ones whose content changed too. Old points are only
+                // deleted once the new ones are in, so a failure part way loses nothing.
+                let mut changed = vec![];
+                let mut stale: Vec<Filter> = vec![];
+                for path in msg.paths {
+                    let key = path.display().to_string();
+                    match indexed.remove(&key) {
+                        Some(file) if file.modified_ms.is_some() && file.modified_ms == modified_ms(&path) => {
+                            report.unchanged += 1;
+                        }
+                        Some(file) => match load_file(&path) {
+                            // Touched but not changed
+                            Ok(documents) if same_file(&documents, &file.hash) => report.unchanged += 1,
+                            Ok(documents) => {
+                                report.updated += 1;
+                                // The new points have the new hash, and may have replaced some old ones
+                                let mut filter = Filter::default().must(Condition::keyword(PATH_FIELD, &key));
+                                if let Some(document) = documents.first() {
+                                    let hash = &document.metadata().file_hash;
+                                    filter = filter.must_not(Condition::keyword(HASH_FIELD, hash));
+                                }
+                                stale.push(filter);
+                                changed.extend(documents);
+                            }
+                            Err(e) => eprintln!("Indexer      : Skipping {}: {}", key, e),
+                        },
+                        None => match load_file(&path) {
+                            Ok(documents) => {
+                                report.added += 1;
+                                changed.extend(documents);
+                            }
+                            Err(e) => eprintln!("Indexer      : Skipping {}: {}", key, e),
+                        },
+                    }
+                }
+
+                // Whatever's left was indexed before but isn't on disk any more
+                for key in indexed.into_keys() {
+                    if !Path::new(&key).exists() {
+                        report.removed += 1;
+                        stale.push(Filter::default().must(Condition::keyword(PATH_FIELD, &key)));
+                    }
+                }
+
+                if !changed.is_empty() {
+                    ensure_collection(&store, &embedding.dimension, &collection_name).await?;
+                }
+
+                let points: Vec<Point> = changed
+                    .iter()
+                    .flat_map(|document| {
+                        chunker
+                            .chunk(document)
+                            .into_iter()
+                            .enumerate()
+                            .map(move |(index, chunk)| Point::new(document, index, chunk))
+                    })
+                    .collect();
+                report.chunks = points.len();
+
+                // Embedding runs ahead of upserting by up to `in_flight` batches
+                let mut embedded = stream::iter(points.chunks(batch_size))
+                    .map(|batch| {
+                        let texts = batch.iter().map(|point| point.chunk.text.clone()).collect();
+                        embedding.batch.send(EmbedBatch(texts))
+                    })
+                    .buffered(in_flight);
+
+                for (i, batch) in points.chunks(batch_size).enumerate() {
+                    let vectors = embedded
+                        .next()
+                        .await
+                        .ok_or_else(|| anyhow!("Embedding stopped early"))???;
+
+                    let batch_points = batch
+                        .iter()
+                        .zip(vectors)
+                        .map(|(point, vector)| vector_store::Point {
+                            id: point.id(),
+                            vector,
+                            payload: point.payload(),
+                        })
+                        .collect();
+                    store
+                        .send(Upsert {
+                            collection_name: collection_name.clone(),
+                            points: batch_points,
+                        })
+                        .await??;
+                    if let Some(keywords) = &keywords {
+                        keywords
+                            .send(AddChunks {
+                                collection_name: collection_name.clone(),
+                                chunks: batch.iter().map(Point::keyword_chunk).collect(),
+                            })
+                            .await??;
+                    }
+
+                    println!(
+                        "Indexer      : Upserted {}/{} chunks",
+                        i * batch_size + batch.len(),
+                        points.len()
+                    );
+                }
+
+                for filter in stale {
+                    if let Some(keywords) = &keywords {
+                        keywords
+                            .send(RemoveMatching {
+                                collection_name: collection_name.clone(),
+                                filter: filter.clone(),
+                            })
+                            .await??;
+                    }
+                    store
+                        .send(DeleteMatching {
+                            collection_name: collection_name.clone(),
+                            filter,
+                        })
+                        .await??;
+                }
+
+                if report.chunks > 0 {
+                    if let Ok(stats) = embedding.stats.send(GetStats).await {
+                        println!("Indexer      : Embedding so far: {}", stats);
+                    }
+                }
+                println!("Indexer      : {}", report);
+                Ok(report)
+            }
+            .await;
+
+            // Saved once per run, however far it got, rather than after every batch
+            let stored = store
+                .send(vector_store::Flush)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|flushed| flushed);
+            let flushed = match &keywords {
+                Some(keywords) => keywords
+                    .send(Flush)
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|flushed| flushed),
+                None => Ok(()),
+            };
+            let report = result?;
+            stored?;
+            flushed?;
+            Ok(report)
+        })
+    }
+}
+
+/// Creates the collection to fit the model's vectors, or makes sure an existing one does.
+async fn ensure_collection(
+    store: &Addr<VectorDb>,
+    embedding: &Recipient<GetDimension>,
+    collection_name: &str,
+) -> Result<()> {
+    let dimension = embedding.send(GetDimension).await? as u64;
+    store
+        .send(EnsureCollection {
+            collection_name: collection_name.to_string(),
+            dimension,
+        })
+        .await??;
+
+    let details = store
+        .send(CollectionInfo {
+            collection_name: collection_name.to_string(),
+        })
+        .await??;
+    if details.dimension != dimension {
+        bail!(
+            "{} holds {} dimensional vectors, but the embedding model makes {}. Index into a new collection instead.",
+            collection_name,
+            details.dimension,
+            dimension
+        );
+    }
+    // Search scores and thresholds assume cosine similarity
+    if details.distance != Distance::Cosine {
+        bail!(
+            "{} compares vectors by {} distance, but documents need cosine. Index into a new collection instead.",
+            collection_name,
+            details.distance
+        );
+    }
+    Ok(())
+}
+
+/// Stable across runs, so indexing a file again replaces its points.
+fn point_id(document: &str, index: usize) -> u64 {
+    let digest = Sha256::digest(format!("{}#{}", document, index).as_bytes());
+    let mut id = [0; 8];
+    id.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(id)
+}
+
+/// Gives the keyword index the points already in the collection, if it has none of
+/// them, eg. because the collection was indexed before keywords were or `keywords/`
+/// was lost. Unchanged files aren't indexed again, so they'd never get there otherwise.
+async fn backfill_keywords(
+    store: &Addr<VectorDb>,
+    keywords: &Addr<KeywordIndex>,
+    collection_name: &str,
+) -> Result<()> {
+    if keywords.send(ChunkCount(collection_name.to_string())).await?? > 0 {
+        return Ok(());
+    }
+
+    let payloads = store
+        .send(Scroll {
+            collection_name: collection_name.to_string(),
+            fields: POINT_FIELDS.iter().map(|field| field.to_string()).collect(),
+        })
+        .await??;
+    let chunks: Vec<KeywordChunk> = payloads.into_iter().filter_map(stored_keyword_chunk).collect();
+    if chunks.is_empty() {
+        return Ok(());
+    }
+
+    println!("Indexer      : Adding {} indexed chunks to the keyword index", chunks.len());
+    keywords
+        .send(AddChunks {
+            collection_name: collection_name.to_string(),
+            chunks,
+        })
+        .await?
+}
+
+/// The keyword chunk for a point that's already stored, under the same id.
+fn stored_keyword_chunk(mut payload: Payload) -> Option<KeywordChunk> {
+    let document = payload.get(DOCUMENT_FIELD)?.as_str()?;
+    let index = payload.get(CHUNK_INDEX_FIELD)?.as_u64()? as usize;
+    let id = point_id(document, index);
+    let serde_json::Value::String(text) = payload.remove(TEXT_FIELD)? else {
+        return None;
+    };
+    Some(KeywordChunk { id, text, payload })
+}
+
+fn same_file(documents: &[Document], hash: &str) -> bool {
+    documents
+        .first()
+        .map_or(false, |document| document.metadata().file_hash == hash)
+}
+
+/// Every file the collection has points for, by path.
+async fn indexed_files(
+    store: &Addr<VectorDb>,
+    collection_name: &str,
+) -> Result<HashMap<String, IndexedFile>> {
+    let payloads = store
+        .send(Scroll {
+            collection_name: collection_name.to_string(),
+            fields: vec![PATH_FIELD.into(), HASH_FIELD.into(), MODIFIED_FIELD.into()],
+        })
+        .await??;
+
+    let mut files: HashMap<String, IndexedFile> = HashMap::new();
+    for payload in payloads {
+        let (Some(path), Some(hash)) = (
+            payload.get(PATH_FIELD).and_then(|path| path.as_str()),
+            payload.get(HASH_FIELD).and_then(|hash| hash.as_str()),
+        ) else {
+            continue;
+        };
+        let file = IndexedFile {
+            hash: hash.to_string(),
+            modified_ms: payload.get(MODIFIED_FIELD).and_then(|modified| modified.as_u64()),
+        };
+
+        // Every chunk of a file carries the same hash and modification time, unless an
+        // earlier run stopped part way. Then it can't be trusted, so it's loaded again.
+        match files.get_mut(path) {
+            Some(indexed) if indexed.hash != file.hash => {
+                indexed.hash = String::new();
+                indexed.modified_ms = None;
+            }
+            Some(_) => {}
+            None => {
+                files.insert(path.to_string(), file);
+            }
+        }
+    }
+    Ok(files)
+}
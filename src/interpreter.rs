@@ -1,29 +1,97 @@
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use actix::prelude::*;
-use serde::Deserialize;
+use actix::{dev::ToEnvelope, prelude::*};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use crate::{
-    audio_player::{Status, StatusRequest},
-    code_writer::{Code, CodeWriter},
-    vectordb_qdrant::{QdrantStore, SearchRequest},
+    audio_player::{Activity, Status, StatusRequest},
+    citation::{Citations, Source},
+    code_writer::Code,
+    conversation::Lifecycle,
+    document_loader::{DirectoryLoader, DOCUMENT_PATTERN},
+    embedding::{EmbeddingQuery, GetDimension},
+    indexer::{Index, Indexer, DOCUMENT_FIELD, END_FIELD, LOCATION_FIELD, PATH_FIELD, START_FIELD},
+    keyword_index::{DropCollection, Fusion, KeywordIndex, KeywordSearch},
+    watcher::{Watch, Watcher},
+    session::{Entry, Record},
+    vector_store::{
+        CollectionInfo, CreateCollection, DeleteCollection, Distance, ListCollections, SearchHit,
+        SearchRequest, VectorDb,
+    },
 };
 
-#[derive(Debug, Deserialize)]
+/// How many search results are passed back to the LLM, unless set with `top_k`
+pub const DEFAULT_TOP_K: u64 = 5;
+
+/// With hybrid search, each side fetches this many times `top_k` results to fuse
+const CANDIDATES_PER_RESULT: u64 = 3;
+
+/// Where documents are indexed when the LLM doesn't name a collection
+pub const DEFAULT_COLLECTION: &str = "documents";
+
+/// The doc comments here are sent to the LLM as function descriptions.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "type")]
 pub enum Action {
+    /// Search through a collection of the user's information
     Search { query: String, collection: String },
+    /// Create a file with the given content
     Writetofile { filename: String, content: String },
+    /// Retrieve documents from the path the user entered
     RetrieveDocuments,
-    IndexDocuments,
+    /// Index the retrieved documents so they can be searched
+    IndexDocuments {
+        /// Defaults to "documents"
+        collection: Option<String>,
+    },
+    /// Ask the user to type something in
     GetStdInput { prompt: String },
+    /// Make a new, empty collection, eg. a knowledge base for a project
+    CreateCollection {
+        name: String,
+        /// Leave out to fit the embedding model
+        dimension: Option<u64>,
+        /// Defaults to cosine
+        distance: Option<Distance>,
+    },
+    /// List the collections that can be searched
+    ListCollections,
+    /// Say how many points a collection has and how its vectors are compared
+    CollectionInfo { name: String },
+    /// Delete a collection and everything in it. Only do this when the user asks for it;
+    /// they're asked to confirm before anything is deleted.
+    DeleteCollection { name: String },
+}
+
+impl Action {
+    /// Build an action from a function call, where the function name is the action type.
+    pub fn from_function_call(name: &str, arguments: &str) -> Result<Self> {
+        let mut value: serde_json::Value = if arguments.trim().is_empty() {
+            serde_json::json!({})
+        } else {
+            serde_json::from_str(arguments)?
+        };
+        value
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Arguments to {} are not an object", name))?
+            .insert("type".into(), name.into());
+        Ok(serde_json::from_value(value)?)
+    }
 }
 
-enum Ting {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Ting {
     Documents(Vec<String>),
     Input(String),
+    /// The directory the documents above it came from
+    Folder(String),
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,12 +103,53 @@ pub struct ThoughtActions {
 #[rtype(result = "Result<()>")]
 pub struct Text(pub String);
 
+impl From<String> for Text {
+    fn from(text: String) -> Self {
+        Self(text)
+    }
+}
+
+/// Actions that have already been parsed, eg. from function calls.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct Actions(pub Vec<Action>);
+
 pub struct Interpreter {
-    // code_writer: Addr<CodeWriter>,
-    // qdrant: Addr<QdrantStore>,
-    observations: Arc<Mutex<Vec<String>>>,
+    /// Needed for `Action::Writetofile`
+    code_writer: Option<Recipient<Code>>,
+    /// Needed for `Action::Search`
+    search: Option<Search>,
+    top_k: u64,
+    /// Searched alongside the vectors, if set
+    keywords: Option<Addr<KeywordIndex>>,
+    fusion: Fusion,
+    /// Labels search results so the LLM can cite them
+    citations: Option<Citations>,
+    /// Needed for `Action::IndexDocuments`
+    indexer: Option<Addr<Indexer>>,
+    /// Keeps indexed folders up to date, if set
+    watcher: Option<Addr<Watcher>>,
+    /// Oldest first, as they're handed to the LLM in the order they were made
+    observations: Arc<Mutex<VecDeque<String>>>,
     memory: Arc<Mutex<Vec<Ting>>>,
-    idle: bool,
+    activity: Activity,
+    session: Option<Recipient<Record>>,
+    events: Option<Recipient<Lifecycle>>,
+    /// Asks the user before anything is deleted
+    confirm: Confirm,
+}
+
+/// Asks the user a yes or no question and says whether they said yes.
+pub type Confirm = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Asks on the terminal. Anything but "y" or "yes" is a no.
+pub fn confirm_on_stdin(question: &str) -> bool {
+    println!("Interpreter : {} (y/N)", question);
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
 }
 
 impl Actor for Interpreter {
@@ -48,105 +157,551 @@ impl Actor for Interpreter {
 }
 
 impl Interpreter {
-    pub fn with(
-        // code_writer: Addr<CodeWriter>,
-        // qdrant: Addr<QdrantStore>,
-    ) -> Self {
+    pub fn with() -> Self {
         Self {
-            // code_writer,
-            // qdrant,
-            observations: Arc::new(Mutex::new(vec![])),
+            code_writer: None,
+            search: None,
+            top_k: DEFAULT_TOP_K,
+            keywords: None,
+            fusion: Fusion::default(),
+            citations: None,
+            indexer: None,
+            watcher: None,
+            observations: Arc::new(Mutex::new(VecDeque::new())),
             memory: Arc::new(Mutex::new(vec![])),
-            idle: true,
+            activity: Activity::default(),
+            session: None,
+            events: None,
+            confirm: Arc::new(confirm_on_stdin),
+        }
+    }
+
+    /// How to ask the user to confirm a deletion. Asks on the terminal by default.
+    pub fn confirm(mut self, confirm: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.confirm = Arc::new(confirm);
+        self
+    }
+
+    /// Answer `Action::Writetofile` by writing the file.
+    pub fn writing(mut self, code_writer: Recipient<Code>) -> Self {
+        self.code_writer = Some(code_writer);
+        self
+    }
+
+    /// Report when actions finish and when there are observations for the LLM.
+    pub fn events(mut self, events: Recipient<Lifecycle>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Answer `Action::Search` by embedding the query and looking it up in the vector store.
+    /// The embedding is usually an `EmbeddingModel`.
+    pub fn search<E>(mut self, store: Addr<VectorDb>, embedding: Addr<E>) -> Self
+    where
+        E: Actor + Handler<EmbeddingQuery> + Handler<GetDimension>,
+        E::Context: ToEnvelope<E, EmbeddingQuery> + ToEnvelope<E, GetDimension>,
+    {
+        self.search = Some(Search {
+            store,
+            embedding: embedding.clone().recipient(),
+            dimension: embedding.recipient(),
+        });
+        self
+    }
+
+    /// How many search results to pass back to the LLM.
+    pub fn top_k(mut self, top_k: u64) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Search by keyword as well as by vector, and fuse the two rankings.
+    pub fn keywords(mut self, keywords: Addr<KeywordIndex>) -> Self {
+        self.keywords = Some(keywords);
+        self
+    }
+
+    /// How much the vector and keyword rankings count for in hybrid search.
+    pub fn fusion(mut self, fusion: Fusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+
+    /// Give every search result a citation id, to be resolved when the answer comes back.
+    pub fn citations(mut self, citations: Citations) -> Self {
+        self.citations = Some(citations);
+        self
+    }
+
+    /// Answer `Action::IndexDocuments` by chunking, embedding and upserting the documents.
+    pub fn indexing(mut self, indexer: Addr<Indexer>) -> Self {
+        self.indexer = Some(indexer);
+        self
+    }
+
+    /// Watch every folder that gets indexed, and re-index it as files change.
+    pub fn watching(mut self, watcher: Addr<Watcher>) -> Self {
+        self.watcher = Some(watcher);
+        self
+    }
+
+    /// Record executed actions and state changes to a session.
+    pub fn session(mut self, session: Recipient<Record>) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Pick up where a previous session left off.
+    pub fn restore(self, memory: Vec<Ting>, observations: Vec<String>) -> Self {
+        Self {
+            memory: Arc::new(Mutex::new(memory)),
+            observations: Arc::new(Mutex::new(VecDeque::from(observations))),
+            ..self
         }
     }
 }
 
+/// What `Action::Search` needs to talk to.
+#[derive(Clone)]
+struct Search {
+    store: Addr<VectorDb>,
+    embedding: Recipient<EmbeddingQuery>,
+    dimension: Recipient<GetDimension>,
+}
+
+impl Search {
+    async fn run(&self, query: &str, collection: &str, top_k: u64) -> Result<Vec<SearchHit>> {
+        let vector = self.embedding.send(EmbeddingQuery(query.to_string())).await??;
+        self.store
+            .send(SearchRequest {
+                collection_name: collection.to_string(),
+                vector,
+                limit: top_k,
+                ..Default::default()
+            })
+            .await?
+    }
+
+    /// Runs the vector and keyword searches together and fuses what they find.
+    /// If either search fails, the other's results are used on their own.
+    async fn run_hybrid(
+        &self,
+        query: &str,
+        collection: &str,
+        top_k: u64,
+        keywords: &Addr<KeywordIndex>,
+        fusion: Fusion,
+    ) -> Result<Vec<SearchHit>> {
+        let limit = top_k * CANDIDATES_PER_RESULT;
+        let keyword_search = keywords.send(KeywordSearch {
+            collection_name: collection.to_string(),
+            query: query.to_string(),
+            limit,
+        });
+        let (vector_hits, keyword_hits) =
+            futures::join!(self.run(query, collection, limit), keyword_search);
+
+        let keyword_hits = match keyword_hits {
+            Ok(Ok(hits)) => Some(hits),
+            Ok(Err(e)) => {
+                eprintln!("Interpreter : Keyword search failed: {}", e);
+                None
+            }
+            Err(e) => {
+                eprintln!("Interpreter : Keyword index is gone: {}", e);
+                None
+            }
+        };
+        let vector_hits = match (vector_hits, &keyword_hits) {
+            (Ok(hits), _) => hits,
+            (Err(e), Some(_)) => {
+                eprintln!("Interpreter : Vector search failed, using keywords only: {}", e);
+                vec![]
+            }
+            (Err(e), None) => return Err(e),
+        };
+        Ok(fusion.fuse(vector_hits, keyword_hits.unwrap_or_default(), top_k as usize))
+    }
+}
+
+/// Carries out the actions that manage collections.
+async fn manage_collection(
+    search: &Search,
+    keywords: Option<&Addr<KeywordIndex>>,
+    confirm: &Confirm,
+    action: Action,
+) -> Result<String> {
+    match action {
+        Action::CreateCollection {
+            name,
+            dimension,
+            distance,
+        } => {
+            let dimension = match dimension {
+                Some(dimension) => dimension,
+                // To fit the embedding model, so documents can be indexed into it
+                None => search.dimension.send(GetDimension).await? as u64,
+            };
+            let distance = distance.unwrap_or_default();
+            search
+                .store
+                .send(CreateCollection {
+                    collection_name: name.clone(),
+                    dimension,
+                    distance,
+                })
+                .await??;
+            Ok(format!(
+                "Created {} for {} dimensional vectors, compared by {} distance.",
+                name, dimension, distance
+            ))
+        }
+        Action::ListCollections => {
+            let names = search.store.send(ListCollections).await??;
+            if names.is_empty() {
+                Ok("There are no collections yet.".to_string())
+            } else {
+                Ok(format!("Collections: {}.", names.join(", ")))
+            }
+        }
+        Action::CollectionInfo { name } => {
+            let details = search
+                .store
+                .send(CollectionInfo { collection_name: name })
+                .await??;
+            Ok(details.to_string())
+        }
+        Action::DeleteCollection { name } => {
+            // The answer comes from the user, not the LLM, and reading it blocks
+            let question = format!("Delete the collection {} and everything in it?", name);
+            let confirm = confirm.clone();
+            let confirmed = actix_rt::task::spawn_blocking(move || confirm(&question))
+                .await
+                .unwrap_or(false);
+            if !confirmed {
+                return Ok(format!("The user didn't confirm, so {} was not deleted.", name));
+            }
+            search
+                .store
+                .send(DeleteCollection {
+                    collection_name: name.clone(),
+                })
+                .await??;
+            if let Some(keywords) = keywords {
+                keywords.send(DropCollection(name.clone())).await??;
+            }
+            Ok(format!("Deleted {}.", name))
+        }
+        action => bail!("{:?} doesn't manage collections", action),
+    }
+}
+
+/// Lays out search results for the LLM, best first, labelled with citation ids if we can.
+/// Without `scored`, eg. for fused results whose scores are on two different scales,
+/// only the order says how relevant they are.
+fn search_observation(
+    query: &str,
+    collection: &str,
+    hits: &[SearchHit],
+    citations: Option<&Citations>,
+    scored: bool,
+) -> String {
+    if hits.is_empty() {
+        return format!("No results in {} for {:?}.", collection, query);
+    }
+
+    let labels: Vec<String> = hits
+        .iter()
+        .enumerate()
+        .map(|(i, hit)| match (citations, source(hit)) {
+            (Some(citations), Some(source)) => citations.cite(source),
+            _ => (i + 1).to_string(),
+        })
+        .collect();
+
+    let mut observation = format!("Results in {} for {:?}:", collection, query);
+    if let Some(example) = citations.and_then(|_| hits.iter().find_map(source)) {
+        observation.push_str(&format!(
+            " Cite the ones you use by putting their id in brackets, eg. [{}].",
+            example.id()
+        ));
+    }
+    for (label, hit) in labels.iter().zip(hits) {
+        if scored {
+            observation.push_str(&format!("\n[{}] (score {:.2}) {}", label, hit.score, hit.text));
+        } else {
+            observation.push_str(&format!("\n[{}] {}", label, hit.text));
+        }
+    }
+    observation
+}
+
+/// Where a hit came from, if it was indexed by us.
+fn source(hit: &SearchHit) -> Option<Source> {
+    let offset = |field: &str| Some(hit.payload.get(field)?.as_u64()? as usize);
+    let text = |field: &str| Some(hit.payload.get(field)?.as_str()?.to_string());
+    Some(Source {
+        path: text(PATH_FIELD)?,
+        // The offsets are into the document, so it's needed to tell pages and sections apart
+        document: text(DOCUMENT_FIELD)?,
+        location: text(LOCATION_FIELD),
+        start: offset(START_FIELD)?,
+        end: offset(END_FIELD)?,
+        score: hit.score,
+    })
+}
+
+/// Saves the interpreter's state to the session, if there is one.
+async fn record_state(
+    session: &Option<Recipient<Record>>,
+    memory: &Mutex<Vec<Ting>>,
+    observations: &Mutex<VecDeque<String>>,
+) {
+    if let Some(session) = session {
+        let memory = memory.lock().await.clone();
+        let observations = observations.lock().await.iter().cloned().collect();
+        session.do_send(Record(Entry::Interpreter { memory, observations }));
+    }
+}
+
 impl Handler<Text> for Interpreter {
-    type Result = ResponseFuture<Result<()>>;
+    type Result = ResponseActFuture<Self, Result<()>>;
 
     fn handle(&mut self, msg: Text, _ctx: &mut Self::Context) -> Self::Result {
-        
-        let thought_actions: ThoughtActions =
-        serde_json::from_str(&msg.0).expect("Unable to parse");
+        match serde_json::from_str::<ThoughtActions>(&msg.0) {
+            Ok(thought_actions) => self.run(thought_actions.actions),
+            Err(e) => {
+                eprintln!("Interpreter : Unable to parse {:?}: {}", msg.0, e);
+                if let Some(events) = &self.events {
+                    events.do_send(Lifecycle::ActionsFinished);
+                }
+                let result: Result<()> = Err(e.into());
+                Box::pin(fut::ready(result))
+            }
+        }
+    }
+}
+
+impl Handler<Actions> for Interpreter {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: Actions, _ctx: &mut Self::Context) -> Self::Result {
+        self.run(msg.0)
+    }
+}
 
-        println!("Interpreter : Received {:?}", thought_actions.actions);
+impl Interpreter {
+    fn run(&mut self, actions: Vec<Action>) -> ResponseActFuture<Self, Result<()>> {
+        println!("Interpreter : Received {:?}", actions);
 
-        // let qdrant = self.qdrant.clone();
-        // let code_writer = self.code_writer.clone();
+        let search = self.search.clone();
+        let top_k = self.top_k;
+        let keywords = self.keywords.clone();
+        let fusion = self.fusion;
+        let citations = self.citations.clone();
+        let indexer = self.indexer.clone();
+        let watcher = self.watcher.clone();
+        let confirm = self.confirm.clone();
+        let code_writer = self.code_writer.clone();
 
-        self.idle = false;
+        let job = self.activity.start(format!("{:?}", actions));
 
         let memory = self.memory.clone();
         let observations = self.observations.clone();
+        let session = self.session.clone();
+        let events = self.events.clone();
 
-        let b = Box::pin(
-            async move {
+        let b = async move {
+            // Counted here, as the conversation may be taking them while this runs
+            let mut pushed = 0;
 
-                // If there are actions, execute them
-                for action in thought_actions.actions {
-
-                    match action {
-                        // Action::Writetofile { filename, content } => {
-                        //     println!("Interpreter : Sending to write to file");
-                        //     let _ = code_writer.send(Code { filename, content }).await.unwrap();
-                        //     observations.lock().await.push("value".into());
-                        // }
-                        Action::Search { .. } => {
-                        //     qdrant
-                        //     .send(SearchRequest {
-                        //         collection_name: "test_collection".into(),
-                        //         vector: vec![0.05, 0.61, 0.76, 0.74],
-                        //     })
-                        //     .await
-                        //     .unwrap()
-                        //     .unwrap();
-                        //     observations.lock().await.push("value".into());
-                        }
-                        Action::RetrieveDocuments {} => {
-                            // Get from memory
-                            let docs = if let Ting::Input(yo) = memory.lock().await.pop().unwrap() {
-                                // Retrieve documents
-                                vec!["hello".into()]
-                            } else {
-                                panic!("Where's the stdinput...");
-                            };
-
-                            // Then add documents to memory
-                            println!("Interpreter : Pushing {:?} to memory", docs);
-                            memory.lock().await.push(Ting::Documents(docs));
-                        }
-                        Action::IndexDocuments {} => {
-                            // Get from memory
-                            if let Ting::Documents(docs) = memory.lock().await.pop().unwrap() {
-                                // Then index docs
-                                println!("Indexing docs {:?}", docs);
+            // If there are actions, execute them
+            for action in actions {
+                if let (Some(session), Ok(value)) = (&session, serde_json::to_value(&action)) {
+                    session.do_send(Record(Entry::Action { action: value }));
+                }
+
+                match action {
+                    Action::Writetofile { filename, content } => {
+                        let observation = match &code_writer {
+                            Some(code_writer) => {
+                                println!("Interpreter : Sending to write to file");
+                                let written = code_writer
+                                    .send(Code {
+                                        filename: filename.clone(),
+                                        content,
+                                    })
+                                    .await
+                                    .map_err(anyhow::Error::from)
+                                    .and_then(|written| written);
+                                match written {
+                                    Ok(()) => format!("Wrote {}.", filename),
+                                    Err(e) => {
+                                        eprintln!("Interpreter : Writing {} failed: {}", filename, e);
+                                        format!("Writing {} failed: {}", filename, e)
+                                    }
+                                }
+                            }
+                            None => "Writing files is not available.".to_string(),
+                        };
+                        observations.lock().await.push_back(observation);
+                        pushed += 1;
+                    }
+                    Action::Search { query, collection } => {
+                        let observation = match &search {
+                            Some(search) => {
+                                let hits = match &keywords {
+                                    Some(keywords) => {
+                                        search.run_hybrid(&query, &collection, top_k, keywords, fusion).await
+                                    }
+                                    None => search.run(&query, &collection, top_k).await,
+                                };
+                                match hits {
+                                    Ok(hits) => {
+                                        // So the ids can still be resolved in a resumed session
+                                        if let (Some(session), Some(_)) = (&session, &citations) {
+                                            for source in hits.iter().filter_map(source) {
+                                                session.do_send(Record(Entry::Citation { source }));
+                                            }
+                                        }
+                                        let scored = keywords.is_none();
+                                        search_observation(&query, &collection, &hits, citations.as_ref(), scored)
+                                    }
+                                    Err(e) => {
+                                        eprintln!("Interpreter : Search failed: {}", e);
+                                        format!("Searching {} for {:?} failed: {}", collection, query, e)
+                                    }
+                                }
+                            }
+                            None => "Search is not available.".to_string(),
+                        };
+                        observations.lock().await.push_back(observation);
+                        pushed += 1;
+                    }
+                    Action::RetrieveDocuments {} => {
+                        // Get from memory
+                        let (folder, docs) = if let Ting::Input(path) = memory.lock().await.pop().unwrap() {
+                            // Retrieve documents
+                            let folder = path.trim().to_string();
+                            let docs: Vec<String> = DirectoryLoader::find_files_with_pattern(Path::new(&folder), DOCUMENT_PATTERN)
+                                .paths()
+                                .iter()
+                                .map(|path| path.display().to_string())
+                                .collect();
+                            (folder, docs)
+                        } else {
+                            panic!("Where's the stdinput...");
+                        };
+
+                        // Then add documents to memory, on top of where they came from
+                        println!("Interpreter : Pushing {:?} to memory", docs);
+                        memory.lock().await.push(Ting::Folder(folder));
+                        memory.lock().await.push(Ting::Documents(docs));
+                    }
+                    Action::IndexDocuments { collection } => {
+                        // Get from memory
+                        let docs = if let Ting::Documents(docs) = memory.lock().await.pop().unwrap() {
+                            docs
+                        } else {
+                            panic!("Where's the documents...");
+                        };
+
+                        let folder = {
+                            let mut memory = memory.lock().await;
+                            if matches!(memory.last(), Some(Ting::Folder(_))) {
+                                memory.pop()
                             } else {
-                                panic!("Where's the documents...");
-                            };
-                        }
-                        Action::GetStdInput { prompt } => {
-                            // Prompt
-                            println!("Interpreter : {}", prompt);
-                            
-                            // Get stdinput
-                            let mut input = String::new();
-                            std::io::stdin().read_line(&mut input).unwrap();
-                            println!("Interpreter : You entered {}", input);
-                            
-                            // Store in list
-                            memory.lock().await.push(Ting::Input(input));
-                        }
-                        _ => {}
+                                None
+                            }
+                        };
+
+                        // Then index docs
+                        println!("Interpreter : Indexing docs {:?}", docs);
+                        let collection_name = collection.unwrap_or_else(|| DEFAULT_COLLECTION.into());
+                        let observation = match &indexer {
+                            Some(indexer) => {
+                                let index = Index {
+                                    paths: docs.into_iter().map(PathBuf::from).collect(),
+                                    collection_name: collection_name.clone(),
+                                };
+                                let report = indexer
+                                    .send(index)
+                                    .await
+                                    .map_err(anyhow::Error::from)
+                                    .and_then(|report| report);
+                                match report {
+                                    Ok(report) => {
+                                        // From now on changes to the folder are picked up without asking
+                                        if let (Some(watcher), Some(Ting::Folder(folder))) = (&watcher, folder) {
+                                            watcher.do_send(Watch {
+                                                root: PathBuf::from(folder),
+                                                pattern: DOCUMENT_PATTERN.into(),
+                                                collection_name,
+                                            });
+                                        }
+                                        report.to_string()
+                                    }
+                                    Err(e) => {
+                                        eprintln!("Interpreter : Indexing failed: {}", e);
+                                        format!("Indexing failed: {}", e)
+                                    }
+                                }
+                            }
+                            None => "Indexing is not available.".to_string(),
+                        };
+                        observations.lock().await.push_back(observation);
+                        pushed += 1;
+                    }
+                    action @ (Action::CreateCollection { .. }
+                    | Action::ListCollections
+                    | Action::CollectionInfo { .. }
+                    | Action::DeleteCollection { .. }) => {
+                        let observation = match &search {
+                            Some(search) => {
+                                match manage_collection(search, keywords.as_ref(), &confirm, action).await {
+                                    Ok(observation) => observation,
+                                    Err(e) => {
+                                        eprintln!("Interpreter : Collection action failed: {}", e);
+                                        format!("That didn't work: {}", e)
+                                    }
+                                }
+                            }
+                            None => "Collections are not available.".to_string(),
+                        };
+                        observations.lock().await.push_back(observation);
+                        pushed += 1;
+                    }
+                    Action::GetStdInput { prompt } => {
+                        // Prompt
+                        println!("Interpreter : {}", prompt);
+                        
+                        // Get stdinput
+                        let mut input = String::new();
+                        std::io::stdin().read_line(&mut input).unwrap();
+                        println!("Interpreter : You entered {}", input);
+                        
+                        // Store in list
+                        memory.lock().await.push(Ting::Input(input));
                     }
                 }
-                Ok(())
             }
-        );
 
-        self.idle = true;
+            record_state(&session, &memory, &observations).await;
 
-        b
+            if let Some(events) = events {
+                // One each, so the conversation fetches every one of them
+                for _ in 0..pushed {
+                    events.do_send(Lifecycle::ObservationReady);
+                }
+                events.do_send(Lifecycle::ActionsFinished);
+            }
+            Ok(())
+        };
+
+        Box::pin(b.into_actor(self).map(move |result, act, _ctx| {
+            act.activity.finish(job);
+            result
+        }))
     }
 }
 
@@ -154,13 +709,9 @@ impl Handler<StatusRequest> for Interpreter {
     type Result = Result<Status>;
 
     fn handle(&mut self, _msg: StatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
-        // println!("Interpreter : Received status request. Idle? {}", self.idle);
+        // println!("Interpreter : Received status request. {:?}", self.activity.status());
 
-        if self.idle {
-            Ok(Status::Idle)
-        } else {
-            Ok(Status::Busy)
-        }
+        Ok(self.activity.status())
     }
 }
 
@@ -175,10 +726,15 @@ impl Handler<GetObservations> for Interpreter {
 
     fn handle(&mut self, _msg: GetObservations, _ctx: &mut Context<Self>) -> Self::Result {
         let observations = self.observations.clone();
+        let memory = self.memory.clone();
+        let session = self.session.clone();
         Box::pin(
             async move {
-                let mut m = observations.lock().await;
-                m.pop()
+                let observation = observations.lock().await.pop_front();
+                if observation.is_some() {
+                    record_state(&session, &memory, &observations).await;
+                }
+                observation
             }
         )
     }
@@ -0,0 +1,402 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use actix::prelude::*;
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    indexer::PATH_FIELD,
+    vector_store::{valid_collection_name, Filter, SearchHit},
+};
+
+pub const KEYWORD_DIR: &str = "keywords";
+
+/// BM25 parameters: how quickly repeats of a term stop counting, and how much
+/// long chunks are penalised
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// A chunk to make searchable by keyword, with the same id and payload as its point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeywordChunk {
+    pub id: u64,
+    pub text: String,
+    pub payload: HashMap<String, serde_json::Value>,
+}
+
+/// Add chunks to a collection, replacing any with the same id.
+/// Changes are only saved on `Flush`.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct AddChunks {
+    pub collection_name: String,
+    pub chunks: Vec<KeywordChunk>,
+}
+
+/// Remove the chunks whose payload `field` is any of `values`.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct RemoveWhere {
+    pub collection_name: String,
+    pub field: String,
+    pub values: Vec<String>,
+}
+
+/// Remove the chunks whose payload the filter matches.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct RemoveMatching {
+    pub collection_name: String,
+    pub filter: Filter,
+}
+
+/// Save every collection that changed since it was last saved, eg. at the end of
+/// indexing, rather than rewriting the whole file after every batch.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct Flush;
+
+/// How many chunks a collection has, none if it's never been used.
+#[derive(Message)]
+#[rtype(result = "Result<usize>")]
+pub struct ChunkCount(pub String);
+
+/// Forget a collection entirely.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct DropCollection(pub String);
+
+/// Best matches for the words in `query`, best first.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<SearchHit>>")]
+pub struct KeywordSearch {
+    pub collection_name: String,
+    pub query: String,
+    pub limit: u64,
+}
+
+/// Splits on anything that can't be part of an identifier, so "E0502" and
+/// "max_retries" stay whole.
+pub fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+/// An inverted index over one collection's chunks.
+#[derive(Default)]
+struct Collection {
+    chunks: HashMap<u64, KeywordChunk>,
+    /// Terms in each chunk
+    lengths: HashMap<u64, usize>,
+    /// For each term, how often it appears in each chunk it's in
+    postings: HashMap<String, HashMap<u64, u32>>,
+    total_length: usize,
+}
+
+impl Collection {
+    fn insert(&mut self, chunk: KeywordChunk) {
+        self.remove(chunk.id);
+
+        let terms = terms(&chunk.text);
+        self.total_length += terms.len();
+        self.lengths.insert(chunk.id, terms.len());
+        for term in terms {
+            *self.postings.entry(term).or_default().entry(chunk.id).or_default() += 1;
+        }
+        self.chunks.insert(chunk.id, chunk);
+    }
+
+    fn remove(&mut self, id: u64) {
+        let Some(chunk) = self.chunks.remove(&id) else {
+            return;
+        };
+        self.total_length -= self.lengths.remove(&id).unwrap_or_default();
+        for term in terms(&chunk.text) {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.remove(&id);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        if self.chunks.is_empty() {
+            return vec![];
+        }
+        let count = self.chunks.len() as f32;
+        let average_length = self.total_length as f32 / count;
+
+        let mut scores: HashMap<u64, f32> = HashMap::new();
+        let mut query_terms = terms(query);
+        query_terms.sort();
+        query_terms.dedup();
+        for term in query_terms {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let matching = postings.len() as f32;
+            let idf = (1.0 + (count - matching + 0.5) / (matching + 0.5)).ln();
+            for (id, frequency) in postings {
+                let frequency = *frequency as f32;
+                let length = self.lengths[id] as f32;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(*id).or_default() += idf * frequency * (K1 + 1.0) / (frequency + norm);
+            }
+        }
+
+        let mut ranked: Vec<(u64, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(id, score)| {
+                let chunk = &self.chunks[&id];
+                SearchHit {
+                    text: chunk.text.clone(),
+                    score,
+                    payload: chunk.payload.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A local BM25 index of the same chunks that go into Qdrant, for finding exact
+/// identifiers and error codes that vector search misses.
+///
+/// Each collection is kept in memory and saved to `<dir>/<collection>.json`
+/// on `Flush`, and when the actor stops.
+pub struct KeywordIndex {
+    dir: PathBuf,
+    collections: HashMap<String, Collection>,
+    /// Changed since they were last saved
+    unsaved: HashSet<String>,
+}
+
+impl Actor for KeywordIndex {
+    type Context = Context<Self>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Err(e) = self.flush() {
+            eprintln!("Keywords     : Unable to save: {}", e);
+        }
+    }
+}
+
+impl KeywordIndex {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            collections: HashMap::new(),
+            unsaved: HashSet::new(),
+        })
+    }
+
+    fn path(&self, collection_name: &str) -> Result<PathBuf> {
+        ensure!(
+            valid_collection_name(collection_name),
+            "{:?} isn't a valid collection name",
+            collection_name
+        );
+        Ok(self.dir.join(format!("{}.json", collection_name)))
+    }
+
+    /// Loads the collection from disk the first time it's used.
+    fn collection(&mut self, collection_name: &str) -> Result<&mut Collection> {
+        if !self.collections.contains_key(collection_name) {
+            let mut collection = Collection::default();
+            let path = self.path(collection_name)?;
+            if path.exists() {
+                let chunks: Vec<KeywordChunk> = serde_json::from_slice(&fs::read(&path)?)?;
+                for chunk in chunks {
+                    collection.insert(chunk);
+                }
+                println!("Keywords     : Loaded {} chunks from {}", collection.chunks.len(), path.display());
+            }
+            self.collections.insert(collection_name.to_string(), collection);
+        }
+        Ok(self.collections.get_mut(collection_name).unwrap())
+    }
+
+    fn save(&self, collection_name: &str) -> Result<()> {
+        let Some(collection) = self.collections.get(collection_name) else {
+            return Ok(());
+        };
+        let chunks: Vec<&KeywordChunk> = collection.chunks.values().collect();
+        // Write then rename, so a crash can't leave half a file behind
+        let path = self.path(collection_name)?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_vec(&chunks)?)?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let unsaved: Vec<String> = self.unsaved.iter().cloned().collect();
+        for collection_name in unsaved {
+            self.save(&collection_name)?;
+            self.unsaved.remove(&collection_name);
+        }
+        Ok(())
+    }
+}
+
+impl Handler<AddChunks> for KeywordIndex {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: AddChunks, _ctx: &mut Self::Context) -> Self::Result {
+        let collection = self.collection(&msg.collection_name)?;
+        for chunk in msg.chunks {
+            collection.insert(chunk);
+        }
+        self.unsaved.insert(msg.collection_name);
+        Ok(())
+    }
+}
+
+impl Handler<RemoveWhere> for KeywordIndex {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: RemoveWhere, _ctx: &mut Self::Context) -> Self::Result {
+        if msg.values.is_empty() {
+            return Ok(());
+        }
+        let collection = self.collection(&msg.collection_name)?;
+        let ids: Vec<u64> = collection
+            .chunks
+            .values()
+            .filter(|chunk| {
+                chunk
+                    .payload
+                    .get(&msg.field)
+                    .and_then(|value| value.as_str())
+                    .map_or(false, |value| msg.values.iter().any(|wanted| wanted == value))
+            })
+            .map(|chunk| chunk.id)
+            .collect();
+        for id in ids {
+            collection.remove(id);
+        }
+        self.unsaved.insert(msg.collection_name);
+        Ok(())
+    }
+}
+
+impl Handler<RemoveMatching> for KeywordIndex {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: RemoveMatching, _ctx: &mut Self::Context) -> Self::Result {
+        let collection = self.collection(&msg.collection_name)?;
+        let ids: Vec<u64> = collection
+            .chunks
+            .values()
+            .filter(|chunk| msg.filter.matches(&chunk.payload))
+            .map(|chunk| chunk.id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        for id in ids {
+            collection.remove(id);
+        }
+        self.unsaved.insert(msg.collection_name);
+        Ok(())
+    }
+}
+
+impl Handler<Flush> for KeywordIndex {
+    type Result = Result<()>;
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Self::Context) -> Self::Result {
+        self.flush()
+    }
+}
+
+impl Handler<DropCollection> for KeywordIndex {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: DropCollection, _ctx: &mut Self::Context) -> Self::Result {
+        let path = self.path(&msg.0)?;
+        self.collections.remove(&msg.0);
+        self.unsaved.remove(&msg.0);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Handler<ChunkCount> for KeywordIndex {
+    type Result = Result<usize>;
+
+    fn handle(&mut self, msg: ChunkCount, _ctx: &mut Self::Context) -> Self::Result {
+        Ok(self.collection(&msg.0)?.chunks.len())
+    }
+}
+
+impl Handler<KeywordSearch> for KeywordIndex {
+    type Result = Result<Vec<SearchHit>>;
+
+    fn handle(&mut self, msg: KeywordSearch, _ctx: &mut Self::Context) -> Self::Result {
+        let collection = self.collection(&msg.collection_name)?;
+        Ok(collection.search(&msg.query, msg.limit as usize))
+    }
+}
+
+/// How much each ranking counts for when they're fused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fusion {
+    pub vector: f32,
+    pub keyword: f32,
+    /// Dampens the lead of the top few results; 60 is the usual choice
+    pub k: f32,
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Self {
+            vector: 1.0,
+            keyword: 1.0,
+            k: 60.0,
+        }
+    }
+}
+
+impl Fusion {
+    /// Weighted reciprocal rank fusion: each hit scores `weight / (k + rank)` in every
+    /// ranking it's in, so hits both searches agree on come out on top.
+    ///
+    /// The fused scores only decide the order. Hits keep the score of the search that
+    /// found them, the vector one if both did.
+    pub fn fuse(&self, vector: Vec<SearchHit>, keyword: Vec<SearchHit>, limit: usize) -> Vec<SearchHit> {
+        let mut fused: Vec<(f32, SearchHit)> = vec![];
+        let mut positions: HashMap<(Option<String>, String), usize> = HashMap::new();
+
+        for (weight, hits) in [(self.vector, vector), (self.keyword, keyword)] {
+            for (rank, hit) in hits.into_iter().enumerate() {
+                let score = weight / (self.k + rank as f32 + 1.0);
+                // The same chunk comes back from both, with the same text and payload
+                let key = (hit.payload.get(PATH_FIELD).map(|path| path.to_string()), hit.text.clone());
+                match positions.get(&key) {
+                    Some(&i) => fused[i].0 += score,
+                    None => {
+                        positions.insert(key, fused.len());
+                        fused.push((score, hit));
+                    }
+                }
+            }
+        }
+
+        fused.sort_by(|a, b| b.0.total_cmp(&a.0));
+        fused.into_iter().take(limit).map(|(_, hit)| hit).collect()
+    }
+}
@@ -0,0 +1,25 @@
+pub mod audio_player;
+pub mod chat_backend;
+pub mod chunker;
+pub mod citation;
+pub mod code_writer;
+pub mod conversation;
+pub mod history;
+pub mod indexer;
+pub mod interpreter;
+pub mod keyword_index;
+pub mod llm;
+pub mod loader;
+pub mod sentence;
+pub mod session;
+pub mod stt;
+pub mod token_processor;
+pub mod tts;
+pub mod tts_polly;
+pub mod vector_store;
+pub mod vectordb_local;
+pub mod vectordb_qdrant;
+pub mod watcher;
+pub mod embedding;
+pub mod document_loader;
+pub mod fence_parser;
@@ -1,30 +1,49 @@
 use std::sync::Arc;
 
-use actix::{Actor, Addr, Context, Handler, Message, ResponseActFuture, WrapFuture, ActorFutureExt};
+use actix::{Actor, Addr, Context, Handler, Message, Recipient, ResponseActFuture, WrapFuture, ActorFutureExt};
 use async_openai::types::{
-    ChatCompletionRequestMessage,
-    ChatCompletionResponseStreamMessage, CreateChatCompletionRequestArgs, Role,
+    ChatCompletionFunctions, ChatCompletionFunctionsArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestMessageArgs, FunctionCall, Role,
 };
-use futures::StreamExt;
-use serde::Deserialize;
+use futures::{
+    stream::{AbortHandle, Abortable},
+    StreamExt,
+};
+use schemars::gen::SchemaSettings;
 use anyhow::Result;
 
-use crate::{token_processor::{Token, TokenProcessorActor}, audio_player::{StatusRequest, Status}};
+use crate::{
+    audio_player::{Activity, Cancel, Status, StatusRequest},
+    chat_backend::{ChatBackend, ChatRequest, OpenAiBackend},
+    conversation::Lifecycle,
+    history::{self, History, Overflow, DEFAULT_BUDGET},
+    session::{Entry, Record},
+    interpreter::{Action, Actions},
+    token_processor::{Flush, Token, TokenProcessorActor},
+};
 
 pub const INITIAL_PROMPT: &str = include_str!("initial_prompt.md");
 
-#[derive(Debug, Deserialize)]
-pub struct ChatChoiceDelta {
-    pub index: u32,
-    pub delta: ChatCompletionResponseStreamMessage,
-    pub finish_reason: Option<String>,
-}
+/// Appended to a reply that was cut off, so the LLM knows it wasn't heard in full.
+const TRUNCATED_MARKER: &str = "\n[interrupted by the user]";
+
+const SUMMARY_PROMPT: &str = "Summarise the conversation below in a few sentences. \
+Keep any names, facts and decisions that may matter later on.";
 
 pub struct LlmActor {
     token_proc: Addr<TokenProcessorActor>,
-    client: Arc<async_openai::Client>,
-    messages: Vec<ChatCompletionRequestMessage>,
-    idle: bool,
+    backend: Arc<dyn ChatBackend>,
+    history: History,
+    activity: Activity,
+    /// When set, actions are advertised as functions and function calls go straight here.
+    /// Otherwise we rely on the prompt to get ```json fences out of the model.
+    interpreter: Option<Recipient<Actions>>,
+    session: Option<Recipient<Record>>,
+    /// Stops the reply currently being streamed, tagged with the number of that reply
+    in_flight: Option<(u64, AbortHandle)>,
+    /// How many replies have been started, to tell them apart
+    replies: u64,
+    events: Option<Recipient<Lifecycle>>,
 }
 
 #[derive(Message)]
@@ -42,55 +61,151 @@ impl Handler<ChatMessage> for LlmActor {
         println!("LLM         : Received {:?}", msg.0);
 
         // Prepare the request
-        let message = ChatCompletionRequestMessage {
-            content: msg.0,
-            role: msg.1,
-            name: None,
-        };
+        let message = ChatCompletionRequestMessageArgs::default()
+            .content(msg.0)
+            .role(msg.1)
+            .build()
+            .unwrap();
 
         // Update state
-        self.idle = false;
-        self.messages.push(message);
+        let job = self.activity.start(format!("Replying to {:?}", message.role));
+        self.emit(Lifecycle::LlmStarted);
+        self.record(&message);
+        self.history.push(message);
+
+        // Make room for the reply
+        let dropped = self.history.trim();
+        if !dropped.is_empty() {
+            println!("LLM         : History over budget, moving {} old messages out", dropped.len());
+        }
+        let summarise = !dropped.is_empty() && self.history.overflow() == Overflow::Summarise;
 
         // Clone the actors for the async task
         let token_proc = self.token_proc.clone();
-        let client = self.client.clone();
-        let messages = self.messages.clone();
+        let interpreter = self.interpreter.clone();
+        let events = self.events.clone();
+        let backend = self.backend.clone();
+        let mut messages = self.history.messages().to_vec();
+
+        let (abort, registration) = AbortHandle::new_pair();
+        self.replies += 1;
+        let reply = self.replies;
+        if let Some((_, previous)) = self.in_flight.replace((reply, abort.clone())) {
+            previous.abort();
+        }
 
         Box::pin(async move {
+            // Fold the dropped turns into a summary, if we're doing that
+            let summary = if summarise {
+                match summarise_messages(backend.as_ref(), &dropped).await {
+                    Ok(summary) => {
+                        let summary = history::summary_message(&summary);
+                        history::insert_summary(&mut messages, summary.clone());
+                        Some(summary)
+                    }
+                    Err(e) => {
+                        eprintln!("LLM         : Unable to summarise, dropping instead: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
             // Set up the request
-            let request = CreateChatCompletionRequestArgs::default()
-                .model("gpt-4")
-                .messages(messages)
-                .stream(true)
-                .build()
-                .unwrap();
+            let request = ChatRequest {
+                messages,
+                functions: if interpreter.is_some() { action_functions() } else { vec![] },
+            };
 
-            // Make the request
-            let mut response = client.chat().create_stream(request).await.unwrap();
-            
-            // Create buffer to store the reply
+            // Create buffers to store the reply
             let mut content: Vec<String> = Vec::with_capacity(1024);
+            let mut call_name = String::new();
+            let mut call_arguments = String::new();
+
+            // Make the request and process the stream
+            let streamed: Result<()> = async {
+                let mut response = Abortable::new(backend.stream(request).await?, registration);
+                while let Some(delta) = response.next().await {
+                    let delta = delta?;
+
+                    if abort.is_aborted() {
+                        break;
+                    }
+
+                    if let Some(token) = delta.content {
+                        // Send to the processor
+                        token_proc.send(Token(token.clone())).await?;
+
+                        // Update the reply buffer
+                        content.push(token);
+                    }
+
+                    // Function calls arrive in pieces too
+                    if let Some(function_call) = delta.function_call {
+                        call_name.extend(function_call.name);
+                        call_arguments.extend(function_call.arguments);
+                    }
+                }
+                Ok(())
+            }
+            .await;
 
-            // Process the stream
-            while let Some(result) = response.next().await {
-                let mut response = result.expect("Error while reading response");
-                let something = response.choices.pop().unwrap();
+            // The processor has already thrown the rest away if we were cancelled.
+            // Otherwise it's flushed even if the stream broke off, so a half-open
+            // block doesn't run into the next reply.
+            let cancelled = abort.is_aborted();
+            if !cancelled {
+                token_proc.send(Flush).await?;
+            }
+            streamed?;
 
-                if let Some(token) = something.delta.content {
-                    // Send to the processor
-                    token_proc.send(Token(token.clone())).await.unwrap();
+            let function_call = if call_name.is_empty() || cancelled {
+                None
+            } else {
+                Some(FunctionCall { name: call_name, arguments: call_arguments })
+            };
 
-                    // Update the reply buffer
-                    content.push(token);
+            if let (Some(interpreter), Some(function_call)) = (interpreter, &function_call) {
+                match Action::from_function_call(&function_call.name, &function_call.arguments) {
+                    Ok(action) => {
+                        if let Some(events) = &events {
+                            events.do_send(Lifecycle::ActionsStarted);
+                        }
+                        interpreter.do_send(Actions(vec![action]));
+                    }
+                    Err(e) => eprintln!("LLM         : Invalid function call {:?}: {}", function_call, e),
                 }
             }
 
-            content.into_iter().collect()
-        }.into_actor(self).map(|content, act, _ctx| {
+            let mut content = content.into_iter().collect::<String>();
+            if cancelled {
+                content.push_str(TRUNCATED_MARKER);
+            }
+
+            Ok::<_, anyhow::Error>((content, function_call, summary))
+        }.into_actor(self).map(move |result, act, _ctx| {
+            act.activity.finish(job);
+            act.emit(Lifecycle::LlmFinished);
+
+            // A cancel and a newer reply may have come in since, so only let go of our own handle
+            if matches!(act.in_flight, Some((in_flight, _)) if in_flight == reply) {
+                act.in_flight = None;
+            }
+            let (content, function_call, summary) = result?;
+
             // Update the states
-            act.messages.push(ChatCompletionRequestMessage { role: Role::Assistant, content, name: None });
-            act.idle = true;
+            if let Some(summary) = summary {
+                act.history.insert_summary(summary);
+            }
+            let mut message = ChatCompletionRequestMessageArgs::default();
+            message.role(Role::Assistant).content(content);
+            if let Some(function_call) = function_call {
+                message.function_call(function_call);
+            }
+            let message = message.build()?;
+            act.record(&message);
+            act.history.push(message);
             Ok(())
         }))
     }
@@ -98,13 +213,142 @@ impl Handler<ChatMessage> for LlmActor {
 
 impl LlmActor {
     pub fn with(token_proc: Addr<TokenProcessorActor>) -> Self {
-        let client = async_openai::Client::new();
+        Self::with_backend(token_proc, OpenAiBackend::new("gpt-4"))
+    }
+
+    pub fn with_backend(token_proc: Addr<TokenProcessorActor>, backend: impl ChatBackend + 'static) -> Self {
         Self {
             token_proc,
-            client: Arc::new(client),
-            messages: vec![],
-            idle: true,
+            backend: Arc::new(backend),
+            history: History::new(DEFAULT_BUDGET, Overflow::Truncate),
+            activity: Activity::default(),
+            interpreter: None,
+            session: None,
+            in_flight: None,
+            replies: 0,
+            events: None,
+        }
+    }
+
+    /// Report when replies start and finish.
+    pub fn events(mut self, events: Recipient<Lifecycle>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    fn emit(&self, event: Lifecycle) {
+        if let Some(events) = &self.events {
+            events.do_send(event);
+        }
+    }
+
+    /// Record every message to a session.
+    pub fn session(mut self, session: Recipient<Record>) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Pick up a conversation where a previous session left off.
+    pub fn restore(mut self, messages: Vec<ChatCompletionRequestMessage>) -> Self {
+        for message in messages {
+            self.history.push(message);
+        }
+        self.history.trim();
+        self
+    }
+
+    fn record(&self, message: &ChatCompletionRequestMessage) {
+        if let Some(session) = &self.session {
+            session.do_send(Record(Entry::message(message)));
+        }
+    }
+
+    /// Keep the history within `budget` tokens, dealing with older turns as `overflow` says.
+    pub fn budget(mut self, budget: usize, overflow: Overflow) -> Self {
+        self.history = History::new(budget, overflow);
+        self
+    }
+
+    /// Use function calling for actions instead of ```json fences.
+    pub fn functions(mut self, interpreter: Recipient<Actions>) -> Self {
+        self.interpreter = Some(interpreter);
+        self
+    }
+}
+
+async fn summarise_messages(
+    backend: &dyn ChatBackend,
+    messages: &[ChatCompletionRequestMessage],
+) -> Result<String> {
+    let request = ChatRequest {
+        messages: vec![
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::System)
+                .content(SUMMARY_PROMPT)
+                .build()?,
+            ChatCompletionRequestMessageArgs::default()
+                .role(Role::User)
+                .content(history::transcript(messages))
+                .build()?,
+        ],
+        functions: vec![],
+    };
+
+    let mut response = backend.stream(request).await?;
+    let mut summary = String::new();
+    while let Some(delta) = response.next().await {
+        summary.extend(delta?.content);
+    }
+
+    Ok(summary)
+}
+
+/// One function per `Action` variant, generated from its JSON schema.
+pub fn action_functions() -> Vec<ChatCompletionFunctions> {
+    // Each function's parameters have to stand alone, so nothing can refer to shared definitions
+    let generator = SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    let schema = serde_json::to_value(generator.into_root_schema_for::<Action>()).expect("Schema should serialize");
+
+    schema["oneOf"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|variant| {
+            let mut parameters = variant.clone();
+            let name = variant["properties"]["type"]["enum"][0].as_str()?.to_string();
+            let description = variant["description"].as_str().unwrap_or_default().to_string();
+
+            // The function name already says which variant it is
+            let object = parameters.as_object_mut()?;
+            object.remove("description");
+            if let Some(properties) = object.get_mut("properties").and_then(|p| p.as_object_mut()) {
+                properties.remove("type");
+            }
+            if let Some(required) = object.get_mut("required").and_then(|r| r.as_array_mut()) {
+                required.retain(|field| field != "type");
+            }
+
+            ChatCompletionFunctionsArgs::default()
+                .name(name)
+                .description(description)
+                .parameters(parameters)
+                .build()
+                .ok()
+        })
+        .collect()
+}
+
+impl Handler<Cancel> for LlmActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Cancel, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some((_, in_flight)) = self.in_flight.take() {
+            println!("LLM         : Cancelled");
+            in_flight.abort();
         }
+        self.token_proc.do_send(msg);
     }
 }
 
@@ -112,10 +356,6 @@ impl Handler<StatusRequest> for LlmActor {
     type Result = Result<Status>;
 
     fn handle(&mut self, _msg: StatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
-        if self.idle {
-            Ok(Status::Idle)
-        } else {
-            Ok(Status::Busy)
-        }
+        Ok(self.activity.status())
     }
 }
@@ -0,0 +1,258 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use quick_xml::events::Event;
+
+/// Lines of text pulled out of HTML are wrapped at this width
+const TEXT_WIDTH: usize = 120;
+
+/// Where in a file a piece of text came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// 1-based, as you'd see in a PDF viewer
+    Page(u32),
+    /// Headings leading to the section, outermost first, eg. "Setup > Linux"
+    Section(String),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Page(page) => write!(f, "page {}", page),
+            Location::Section(section) => write!(f, "section {}", section),
+        }
+    }
+}
+
+/// Text pulled out of a file, before it becomes a `Document`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub text: String,
+    pub title: Option<String>,
+    pub location: Option<Location>,
+}
+
+impl Part {
+    fn whole(text: String, title: Option<String>) -> Self {
+        Self {
+            text,
+            title,
+            location: None,
+        }
+    }
+}
+
+/// Turns the bytes of one kind of file into text.
+pub trait Loader {
+    /// A file can come out in several parts, eg. one per page or section.
+    fn load(&self, bytes: &[u8]) -> Result<Vec<Part>>;
+}
+
+/// Picks a loader by extension, or failing that by looking at the content.
+pub fn loader_for(path: &Path, bytes: &[u8]) -> Result<Box<dyn Loader>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    let loader: Box<dyn Loader> = match extension.as_deref() {
+        Some("txt") => Box::new(TextLoader),
+        Some("md" | "markdown") => Box::new(MarkdownLoader),
+        Some("html" | "htm") => Box::new(HtmlLoader),
+        Some("pdf") => Box::new(PdfLoader),
+        Some("docx") => Box::new(DocxLoader),
+        _ => sniff(bytes).ok_or_else(|| anyhow!("Unsupported file {}", path.display()))?,
+    };
+    Ok(loader)
+}
+
+fn sniff(bytes: &[u8]) -> Option<Box<dyn Loader>> {
+    if bytes.starts_with(b"%PDF") {
+        return Some(Box::new(PdfLoader));
+    }
+    // DOCX is a zip, and we can't tell it apart from any other zip without opening it
+    if bytes.starts_with(b"PK\x03\x04") {
+        return Some(Box::new(DocxLoader));
+    }
+
+    let text = std::str::from_utf8(bytes).ok()?;
+    let start = text.trim_start().chars().take(14).collect::<String>().to_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        Some(Box::new(HtmlLoader))
+    } else {
+        Some(Box::new(TextLoader))
+    }
+}
+
+pub struct TextLoader;
+
+impl Loader for TextLoader {
+    fn load(&self, bytes: &[u8]) -> Result<Vec<Part>> {
+        // Better a few replacement characters than losing the whole file
+        Ok(vec![Part::whole(String::from_utf8_lossy(bytes).into_owned(), None)])
+    }
+}
+
+/// One part per section, so each chunk knows which heading it's under.
+pub struct MarkdownLoader;
+
+impl Loader for MarkdownLoader {
+    fn load(&self, bytes: &[u8]) -> Result<Vec<Part>> {
+        let text = String::from_utf8_lossy(bytes);
+
+        let mut title = None;
+        let mut headings: Vec<(usize, String)> = vec![];
+        let mut sections = vec![];
+        let mut current = String::new();
+        let mut in_fence = false;
+
+        for line in text.lines() {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+            }
+            let heading = (!in_fence).then(|| heading(line)).flatten();
+
+            match heading {
+                Some((level, heading)) => {
+                    sections.push((section_path(&headings), std::mem::take(&mut current)));
+
+                    if level == 1 && title.is_none() {
+                        title = Some(heading.clone());
+                    }
+                    headings.retain(|(outer, _)| *outer < level);
+                    headings.push((level, heading));
+                    current.push_str(line);
+                    current.push('\n');
+                }
+                None => {
+                    current.push_str(line);
+                    current.push('\n');
+                }
+            }
+        }
+        sections.push((section_path(&headings), current));
+
+        Ok(sections
+            .into_iter()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(section, text)| Part {
+                text,
+                title: title.clone(),
+                location: section.map(Location::Section),
+            })
+            .collect())
+    }
+}
+
+/// Level and text of an ATX heading, eg. `## Setup`.
+fn heading(line: &str) -> Option<(usize, String)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim().to_string()))
+}
+
+fn section_path(headings: &[(usize, String)]) -> Option<String> {
+    if headings.is_empty() {
+        None
+    } else {
+        let names: Vec<&str> = headings.iter().map(|(_, heading)| heading.as_str()).collect();
+        Some(names.join(" > "))
+    }
+}
+
+pub struct HtmlLoader;
+
+impl Loader for HtmlLoader {
+    fn load(&self, bytes: &[u8]) -> Result<Vec<Part>> {
+        let html = String::from_utf8_lossy(bytes);
+        let title = html_title(&html);
+        let text = html2text::from_read(bytes, TEXT_WIDTH);
+        Ok(vec![Part::whole(text, title)])
+    }
+}
+
+fn html_title(html: &str) -> Option<String> {
+    // ASCII only, so offsets into `lower` are offsets into `html`
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = html[start..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+/// One part per page.
+pub struct PdfLoader;
+
+impl Loader for PdfLoader {
+    fn load(&self, bytes: &[u8]) -> Result<Vec<Part>> {
+        let document = lopdf::Document::load_mem(bytes)?;
+        let title = document
+            .trailer
+            .get(b"Info")
+            .and_then(|info| document.dereference(info))
+            .and_then(|(_, info)| info.as_dict())
+            .and_then(|info| info.get(b"Title"))
+            .and_then(|title| title.as_str())
+            .ok()
+            .map(|title| String::from_utf8_lossy(title).trim().to_string())
+            .filter(|title| !title.is_empty());
+
+        let mut parts = vec![];
+        for page in document.get_pages().into_keys() {
+            let text = document.extract_text(&[page])?;
+            if text.trim().is_empty() {
+                continue;
+            }
+            parts.push(Part {
+                text,
+                title: title.clone(),
+                location: Some(Location::Page(page)),
+            });
+        }
+        Ok(parts)
+    }
+}
+
+pub struct DocxLoader;
+
+impl Loader for DocxLoader {
+    fn load(&self, bytes: &[u8]) -> Result<Vec<Part>> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        let mut xml = String::new();
+        archive
+            .by_name("word/document.xml")
+            .map_err(|_| anyhow!("Not a Word document"))?
+            .read_to_string(&mut xml)?;
+
+        // Text lives in <w:t> runs, and paragraphs end with </w:p>
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let mut text = String::new();
+        let mut in_text = false;
+        loop {
+            match reader.read_event()? {
+                Event::Start(tag) if tag.name().as_ref() == b"w:t" => in_text = true,
+                Event::End(tag) if tag.name().as_ref() == b"w:t" => in_text = false,
+                Event::End(tag) if tag.name().as_ref() == b"w:p" => text.push('\n'),
+                Event::Empty(tag) if tag.name().as_ref() == b"w:tab" => text.push('\t'),
+                Event::Text(content) if in_text => text.push_str(&content.unescape()?),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if text.trim().is_empty() {
+            bail!("No text in Word document");
+        }
+        Ok(vec![Part::whole(text, None)])
+    }
+}
@@ -1,32 +1,50 @@
-pub mod audio_player;
-pub mod code_writer;
-pub mod interpreter;
-pub mod llm;
-pub mod stt;
-pub mod token_processor;
-pub mod tts;
-pub mod tts_polly;
-pub mod vectordb_qdrant;
-pub mod embedding;
-pub mod document_loader;
+use actix::prelude::*;
+use actor_demo::audio_player::{AudioPlayerActor, PlaybackMonitor};
+use actor_demo::citation::Citations;
+use actor_demo::code_writer::CodeWriter;
+use actor_demo::conversation::{Conversation, Lifecycle, Start};
+use actor_demo::embedding::{EmbeddingConfig, EmbeddingModel, EmbeddingQuery};
+use actor_demo::indexer::Indexer;
+use actor_demo::interpreter::{Interpreter, Text};
+use actor_demo::keyword_index::{Fusion, KeywordIndex, KEYWORD_DIR};
+use actor_demo::llm::LlmActor;
+use actor_demo::session::{Session, SessionStore, SESSION_DIR};
+use actor_demo::stt::Stt;
+use actor_demo::token_processor::TokenProcessorActor;
+use actor_demo::tts_polly::TtsPollyActor;
+use actor_demo::vector_store::VectorDb;
+use actor_demo::vectordb_local::{LocalStore, VECTOR_DIR};
+use actor_demo::vectordb_qdrant::QdrantStore;
+use actor_demo::watcher::Watcher;
 
-use std::time::Duration;
+/// Starts a new session, or picks up the one named by `--resume <session>`.
+fn open_session() -> (Addr<SessionStore>, Session) {
+    let args: Vec<String> = std::env::args().collect();
+    let resume = args
+        .iter()
+        .position(|arg| arg == "--resume")
+        .map(|i| args.get(i + 1).expect("--resume needs a session name").clone());
 
-use actix::prelude::*;
-use async_openai::types::Role;
-use audio_player::{AudioPlayerActor, Status, StatusRequest};
-use code_writer::CodeWriter;
-use embedding::{EmbeddingModel, EmbeddingQuery};
-use interpreter::{GetObservations, Interpreter, Text};
-use llm::{ChatMessage, LlmActor, INITIAL_PROMPT};
-use stt::{Stt, SttAction};
-use token_processor::TokenProcessorActor;
-use tts_polly::TtsPollyActor;
-use vectordb_qdrant::QdrantStore;
+    let (name, session) = match resume {
+        Some(name) => {
+            let session = SessionStore::load(SESSION_DIR, &name).expect("Unable to resume session");
+            println!("Session      : Resuming {} with {} messages", name, session.messages.len());
+            (name, session)
+        }
+        None => (SessionStore::new_session_name(), Session::default()),
+    };
+
+    let store = SessionStore::open(SESSION_DIR, &name).expect("Unable to open session");
+    (store.start(), session)
+}
 
 #[actix_rt::main]
 async fn main() {
-    let interpreter = Interpreter::with().start();
+    let (session_store, session) = open_session();
+    let interpreter = Interpreter::with()
+        .session(session_store.clone().recipient())
+        .restore(session.memory, session.observations)
+        .start();
     let _  = interpreter.send(Text(r#"{
         "actions": [
             {
@@ -50,75 +68,92 @@ async fn main() {
 
 // #[actix_rt::main]
 // async fn main() {
-//     let embedding = SyncArbiter::start(1, EmbeddingModel::default);
+//     let (session_store, session) = open_session();
+//     let embedding_threads = 2;
+//     let embedding_config = EmbeddingConfig::from_env().unwrap();
+//     let embedding = EmbeddingModel::pool(embedding_threads, embedding_config).unwrap();
+
+//     // The conversation hears from every actor, so it's created before them and started last
+//     let conversation_ctx = Context::<Conversation>::new();
+//     let events = conversation_ctx.address().recipient::<Lifecycle>();
 
 //     // Tools
-//     let qdrant_client: Addr<QdrantStore> = QdrantStore::new().await.start();
+//     // Qdrant if QDRANT_URL is set, otherwise files on this machine
+//     let vector_db = match std::env::var("QDRANT_URL") {
+//         Ok(url) => VectorDb::with(QdrantStore::with_url(&url).await),
+//         Err(_) => VectorDb::with(LocalStore::open(VECTOR_DIR).unwrap()),
+//     }
+//     .start();
 //     let code_writer = CodeWriter.start();
+//     let keywords = KeywordIndex::open(KEYWORD_DIR).unwrap().start();
+//     let indexer = Indexer::with(embedding.clone(), vector_db.clone())
+//         .keywords(keywords.clone())
+//         .in_flight(embedding_threads)
+//         .start();
+//     let watcher = Watcher::with(indexer.clone().recipient()).start();
+//     let citations = Citations::restore(session.citations);
 
 //     // Interpreter
-//     let interpreter = Interpreter::with(code_writer, qdrant_client).start();
+//     let interpreter = Interpreter::with()
+//         .writing(code_writer.recipient())
+//         .search(vector_db, embedding.clone())
+//         .keywords(keywords)
+//         .fusion(Fusion::default())
+//         .indexing(indexer)
+//         .watching(watcher)
+//         .citations(citations.clone())
+//         .session(session_store.clone().recipient())
+//         .restore(session.memory, session.observations)
+//         .events(events.clone())
+//         .start();
 
 //     // Initialise actors
-//     let audio_player = SyncArbiter::start(1, AudioPlayerActor::default);
-//     let tts = TtsPollyActor::with(audio_player.clone()).await.start();
-//     let token_proc = TokenProcessorActor::with(tts.clone(), interpreter.clone()).start();
+//     let playback = PlaybackMonitor::default();
+//     let audio_events = events.clone();
+//     let audio_monitor = playback.clone();
+//     let audio_player = SyncArbiter::start(1, move || {
+//         AudioPlayerActor::with(audio_monitor.clone()).events(audio_events.clone())
+//     });
+//     let tts = TtsPollyActor::with(audio_player.clone())
+//         .await
+//         .events(events.clone())
+//         .start();
+//     let token_proc = TokenProcessorActor::with(tts.clone(), interpreter.clone())
+//         .stream_sentences("speech")
+//         .citations("speech", citations)
+//         .events(events.clone())
+//         .announce("speech", Lifecycle::UtteranceQueued)
+//         .announce("json", Lifecycle::ActionsStarted)
+//         .start();
 
 //     // LLM
-//     let llm = LlmActor::with(token_proc.clone()).start();
+//     let resumed = !session.messages.is_empty();
+//     let llm = LlmActor::with(token_proc.clone())
+//         .session(session_store.clone().recipient())
+//         .restore(session.messages)
+//         .events(events.clone())
+//         .start();
 //     let llm_clone = llm.clone();
 
+//     let stt_events = events.clone();
 //     let stt = SyncArbiter::start(1, move || {
-//         Stt::new(
+//         Stt::with_playback(
 //             "/Users/raimibinkarim/Desktop/ggml-tiny.en.bin".to_string(),
 //             llm_clone.to_owned(),
+//             Some(playback.clone()),
 //         )
+//         .events(stt_events.clone())
 //     });
 
-//     tokio::time::sleep(Duration::from_secs(1)).await;
-    
-//     // Get the ball rolling
-//     let _ = llm
-//         .send(ChatMessage(INITIAL_PROMPT.into(), Role::System))
-//         .await
-//         .unwrap();
-
-//     // Start the turn-based conversation
-//     loop {
-//         // Playing safe first
-//         tokio::time::sleep(Duration::from_secs(2)).await;
-
-//         // Once the system is stabilised
-//         // It is my turn when all the audio player is idle.
-//         let audio_status_request = audio_player.send(StatusRequest).await.unwrap().unwrap();
-//         let tts_status_request = tts.send(StatusRequest).await.unwrap().unwrap();
-//         let token_proc_status_request = token_proc.send(StatusRequest).await.unwrap().unwrap();
-//         let interpreter_status_request = interpreter.send(StatusRequest).await.unwrap().unwrap();
-//         let llm_status_request = llm.send(StatusRequest).await.unwrap().unwrap();
-//         if audio_status_request == Status::Idle
-//             && token_proc_status_request == Status::Idle
-//             && tts_status_request == Status::Idle
-//             && interpreter_status_request == Status::Idle
-//             && llm_status_request == Status::Idle
-//         {
-//             // Check if anything from interpreter
-//             let observation = interpreter.send(GetObservations).await.unwrap();
-
-//             // Continue with what its doing, or ask for input
-//             if let Some(observation) = observation {
-//                 println!("--- observation: {} ---", observation);
-//                 let _ = llm
-//                     .send(ChatMessage(observation, Role::User))
-//                     .await
-//                     .unwrap();
-//             } else {
-//                 // Start recording
-//                 let _ = stt.send(SttAction::RecordUntilSilence).await.unwrap();
-//             }
-//         };
-//     }
+//     // Get the ball rolling, unless we're picking up an old conversation
+//     let conversation = conversation_ctx.run(Conversation::with(
+//         llm.recipient(),
+//         stt.recipient(),
+//         interpreter.recipient(),
+//     ));
+//     conversation.do_send(Start { prompt: !resumed });
 
-//     // Stop system and exit
-//     // Currently unreachable code
-//     // System::current().stop();
+//     // The conversation drives itself from here
+//     tokio::signal::ctrl_c().await.unwrap();
+//     System::current().stop();
 // }
@@ -0,0 +1,107 @@
+/// Words that end with a full stop but don't end a sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "etc", "e.g", "i.e", "a.m", "p.m",
+    "approx", "fig", "inc", "ltd", "mt",
+];
+
+/// Returns the byte index just past the first complete sentence in `text`, if any.
+///
+/// A sentence is only complete once we've seen the whitespace after its final
+/// punctuation, so "3.14" or "example.com/a.b" are never split while they're
+/// still streaming in.
+pub fn next_boundary(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        // A blank line always ends whatever came before it
+        if c == '\n' && matches!(chars.peek(), Some((_, '\n'))) && !text[..i].trim().is_empty() {
+            return Some(i);
+        }
+
+        if !matches!(c, '.' | '!' | '?') {
+            continue;
+        }
+
+        // Swallow the rest of "?!", "..." and closing quotes or brackets
+        let mut end = i + c.len_utf8();
+        while let Some(&(j, next)) = chars.peek() {
+            if matches!(next, '.' | '!' | '?' | '"' | '\'' | ')' | ']' | '”' | '’') {
+                end = j + next.len_utf8();
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        // Need to see the whitespace after it, otherwise it may be a decimal or a URL
+        match chars.peek() {
+            Some((_, next)) if next.is_whitespace() => {}
+            _ => continue,
+        }
+
+        if c == '.' && is_abbreviation(&text[..i], &text[end..]) {
+            continue;
+        }
+
+        return Some(end);
+    }
+
+    None
+}
+
+/// Splits off every complete sentence at the start of `text`.
+/// Returns the sentences and the byte index where the unfinished remainder starts.
+pub fn complete_sentences(text: &str) -> (Vec<&str>, usize) {
+    let mut sentences = vec![];
+    let mut offset = 0;
+
+    while let Some(end) = next_boundary(&text[offset..]) {
+        let sentence = text[offset..offset + end].trim();
+        if !sentence.is_empty() {
+            sentences.push(sentence);
+        }
+        offset += end;
+    }
+
+    (sentences, offset)
+}
+
+fn is_abbreviation(before: &str, after: &str) -> bool {
+    let word = before
+        .rsplit(|c: char| c.is_whitespace() || c == '(')
+        .next()
+        .unwrap_or("");
+
+    // Initials, eg. "J. R. R. Tolkien", but not "I."
+    let mut word_chars = word.chars();
+    if let (Some(first), None) = (word_chars.next(), word_chars.next()) {
+        if first.is_uppercase() && first != 'I' {
+            return true;
+        }
+    }
+
+    // Dotted initialisms, eg. "U.S." or "U.K."
+    if word.contains('.')
+        && word
+            .split('.')
+            .all(|part| part.chars().count() == 1 && part.chars().all(char::is_alphabetic))
+    {
+        return true;
+    }
+
+    // "No. 5", but "I said no. Then" ends a sentence. Wait for the next word to tell.
+    if word.eq_ignore_ascii_case("no") {
+        return after
+            .trim_start()
+            .chars()
+            .next()
+            .map_or(true, |next| next.is_ascii_digit());
+    }
+
+    // The company, eg. "Acme Co.", but not a lower case "co."
+    if word == "Co" {
+        return true;
+    }
+
+    ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix::prelude::*;
+use anyhow::{Context as _, Result};
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs, FunctionCall, Role,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{citation::Source, interpreter::Ting};
+
+pub const SESSION_DIR: &str = "sessions";
+const TURNS_FILE: &str = "turns.jsonl";
+
+/// One line of a session file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Entry {
+    /// A message in the conversation with the LLM
+    Message {
+        role: Role,
+        content: String,
+        name: Option<String>,
+        function_call: Option<FunctionCall>,
+    },
+    /// An action the interpreter executed
+    Action { action: serde_json::Value },
+    /// The interpreter's state after it changed
+    Interpreter {
+        memory: Vec<Ting>,
+        observations: Vec<String>,
+    },
+    /// A chunk the LLM was given to cite
+    Citation { source: Source },
+}
+
+impl Entry {
+    pub fn message(message: &ChatCompletionRequestMessage) -> Self {
+        Entry::Message {
+            role: message.role.clone(),
+            content: message.content.clone().unwrap_or_default(),
+            name: message.name.clone(),
+            function_call: message.function_call.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Line {
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Record(pub Entry);
+
+/// Appends every turn of a conversation to `<dir>/<session>/turns.jsonl`.
+pub struct SessionStore {
+    file: File,
+    path: PathBuf,
+}
+
+impl Actor for SessionStore {
+    type Context = Context<Self>;
+}
+
+impl SessionStore {
+    /// Opens the session for appending, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>, session: &str) -> Result<Self> {
+        let session_dir = dir.as_ref().join(session);
+        fs::create_dir_all(&session_dir)?;
+
+        let path = session_dir.join(TURNS_FILE);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        println!("Session      : Recording to {}", path.display());
+
+        Ok(Self { file, path })
+    }
+
+    /// A fresh session name based on the current time.
+    pub fn new_session_name() -> String {
+        format!("session-{}", now_ms())
+    }
+
+    /// Reads a recorded session back, to rebuild the actors from.
+    pub fn load(dir: impl AsRef<Path>, session: &str) -> Result<Session> {
+        let path = dir.as_ref().join(session).join(TURNS_FILE);
+        let file = File::open(&path).with_context(|| format!("No session at {}", path.display()))?;
+
+        let mut restored = Session::default();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line: Line = serde_json::from_str(&line)
+                .with_context(|| format!("Bad entry on line {} of {}", i + 1, path.display()))?;
+
+            match line.entry {
+                Entry::Message { role, content, name, function_call } => {
+                    let mut message = ChatCompletionRequestMessageArgs::default();
+                    message.role(role).content(content);
+                    if let Some(name) = name {
+                        message.name(name);
+                    }
+                    if let Some(function_call) = function_call {
+                        message.function_call(function_call);
+                    }
+                    restored.messages.push(message.build()?);
+                }
+                Entry::Action { .. } => {}
+                Entry::Citation { source } => restored.citations.push(source),
+                Entry::Interpreter { memory, observations } => {
+                    restored.memory = memory;
+                    restored.observations = observations;
+                }
+            }
+        }
+
+        Ok(restored)
+    }
+}
+
+impl Handler<Record> for SessionStore {
+    type Result = ();
+
+    fn handle(&mut self, msg: Record, _ctx: &mut Context<Self>) -> Self::Result {
+        let line = Line {
+            timestamp_ms: now_ms(),
+            entry: msg.0,
+        };
+
+        // Losing a line shouldn't take the conversation down with it
+        let result = serde_json::to_string(&line)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(writeln!(self.file, "{}", json)?));
+        if let Err(e) = result {
+            eprintln!("Session      : Unable to write to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The state of the actors at the end of a recorded session.
+#[derive(Default)]
+pub struct Session {
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    pub memory: Vec<Ting>,
+    pub observations: Vec<String>,
+    /// What the ids in the restored observations refer to
+    pub citations: Vec<Source>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
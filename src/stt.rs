@@ -1,11 +1,14 @@
-use actix::{Actor, Handler, Message, SyncContext, Addr};
+use actix::{Actor, Handler, Message, SyncContext, Addr, Recipient};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Stream, StreamConfig};
 use rubato::{InterpolationParameters, InterpolationType, Resampler, SincFixedIn, WindowFunction};
-use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
 use std::time::{Duration, Instant};
 use whisper_rs::{convert_stereo_to_mono_audio, FullParams, SamplingStrategy, WhisperContext};
 use anyhow::Result;
+use crate::audio_player::{Cancel, PlaybackMonitor};
+use crate::conversation::Lifecycle;
 use crate::llm::{LlmActor, ChatMessage};
 
 const VOLUME_THRESHOLD: f32 = 0.05;
@@ -13,6 +16,51 @@ const SILENCE_DURATION: Duration = Duration::from_secs(1);
 const AUDIO_BUFFER: usize = 512;
 const OUTPUT_SAMPLE_RATE: usize = 16_000; // as required by Whisper
 
+// How long to wait for a sample before checking whether to stop listening
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Barge-in detection while the assistant is talking
+pub const VAD_FRAME: usize = OUTPUT_SAMPLE_RATE / 50; // 20 ms
+const VAD_FRAME_DURATION: Duration = Duration::from_micros((VAD_FRAME * 1_000_000 / OUTPUT_SAMPLE_RATE) as u64);
+pub const BARGE_IN_THRESHOLD: f32 = 0.02; // RMS of a frame
+pub const ECHO_GAIN: f32 = 0.5; // How much of the playback level leaks back into the mic
+pub const BARGE_IN_DURATION: Duration = Duration::from_millis(300);
+const PRE_ROLL: usize = OUTPUT_SAMPLE_RATE / 2; // Keep the start of what the user said
+
+/// Decides, a frame at a time, whether the user is talking over the assistant.
+///
+/// To avoid the assistant interrupting itself, the threshold rises with the
+/// level of what's playing, since some of that leaks back into the mic.
+#[derive(Debug, Default)]
+pub struct BargeInDetector {
+    speech: Duration,
+}
+
+impl BargeInDetector {
+    /// Feed one frame of `VAD_FRAME` samples, along with the playback level at the time.
+    /// Returns true once there's been enough speech to count as a barge-in.
+    pub fn push_frame(&mut self, frame: &[f32], playback_level: f32) -> bool {
+        self.speech = if is_speech(frame, playback_level) {
+            self.speech + VAD_FRAME_DURATION
+        } else {
+            self.speech.saturating_sub(VAD_FRAME_DURATION)
+        };
+        self.speech >= BARGE_IN_DURATION
+    }
+}
+
+/// Whether a frame is louder than the echo of what's playing could explain.
+pub fn is_speech(frame: &[f32], playback_level: f32) -> bool {
+    rms(frame) > BARGE_IN_THRESHOLD + ECHO_GAIN * playback_level
+}
+
+pub fn rms(frame: &[f32]) -> f32 {
+    if frame.is_empty() {
+        return 0.0;
+    }
+    (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt()
+}
+
 pub trait GetInput {
     fn record(&mut self) -> String;
 }
@@ -23,6 +71,8 @@ pub struct Stt {
     audio_receiver: Receiver<f32>,
     stream: cpal::platform::Stream,
     llm: Addr<LlmActor>,
+    playback: Option<PlaybackMonitor>,
+    events: Option<Recipient<Lifecycle>>,
 }
 
 impl Actor for Stt {
@@ -34,6 +84,8 @@ impl Actor for Stt {
 #[rtype(result = "Result<()>")]
 pub enum SttAction {
     RecordUntilSilence,
+    /// Listen while the assistant is talking, and interrupt it if the user talks over it
+    ListenForBargeIn,
     Pause,
 }
 
@@ -46,6 +98,13 @@ impl Handler<SttAction> for Stt {
                 let utterance = self.record();
                 self.llm.do_send(ChatMessage(utterance, async_openai::types::Role::User));
             }
+            SttAction::ListenForBargeIn => {
+                if self.listen_for_barge_in() {
+                    // Whatever was said over the assistant is the next turn
+                    let utterance = self.transcribe();
+                    self.llm.do_send(ChatMessage(utterance, async_openai::types::Role::User));
+                }
+            }
             SttAction::Pause => {
                 self.stream.pause().expect("Failed to pause stream");
             }
@@ -145,6 +204,13 @@ impl GetInput for Stt {
         // Pause the stream
         self.stream.pause().expect("Failed to pause stream");
 
+        self.transcribe()
+    }
+}
+
+impl Stt {
+    /// Run the ASR model over the recorded audio, then clear it.
+    fn transcribe(&mut self) -> String {
         // Not sure how we store this value somewhere in the struct
         // without having to initialise it every time
         let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 0 });
@@ -180,10 +246,13 @@ impl GetInput for Stt {
             .collect::<Vec<String>>()
             .join(" ")
     }
-}
 
-impl Stt {
     pub fn new(path_to_model: String, llm: Addr<LlmActor>) -> Self {
+        Self::with_playback(path_to_model, llm, None)
+    }
+
+    /// `playback` lets STT listen for barge-in while the assistant is talking.
+    pub fn with_playback(path_to_model: String, llm: Addr<LlmActor>, playback: Option<PlaybackMonitor>) -> Self {
         let ctx = WhisperContext::new(&path_to_model).expect("failed to load model");
 
         let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 0 });
@@ -205,9 +274,17 @@ impl Stt {
             audio_receiver,
             stream,
             llm,
+            playback,
+            events: None,
         }
     }
 
+    /// Report barge-ins, so the conversation knows the user has the floor.
+    pub fn events(mut self, events: Recipient<Lifecycle>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
     /// Simple voice activity detection using silence duration.
     ///
     /// Note that this function will block the main thread,
@@ -216,7 +293,7 @@ impl Stt {
     fn run_voice_activity_detection(&mut self) {
         let mut last_voice_activity = Instant::now();
         while last_voice_activity.elapsed() < SILENCE_DURATION {
-            if let Ok(sample) = self.audio_receiver.try_recv() {
+            if let Ok(sample) = self.audio_receiver.recv_timeout(POLL_INTERVAL) {
                 // Check for voice activity
                 if sample.abs() > VOLUME_THRESHOLD {
                     last_voice_activity = Instant::now();
@@ -227,4 +304,61 @@ impl Stt {
             }
         }
     }
+
+    /// Listen for the user talking over the assistant for as long as audio is playing.
+    /// On sustained speech, cancels the assistant and keeps recording until silence.
+    /// Returns whether the user barged in, in which case `audio_data` holds what they said.
+    fn listen_for_barge_in(&mut self) -> bool {
+        let Some(playback) = self.playback.clone() else {
+            return false;
+        };
+
+        self.stream.play().expect("Failed to start recording");
+
+        let mut pre_roll: VecDeque<f32> = VecDeque::with_capacity(PRE_ROLL);
+        let mut frame: Vec<f32> = Vec::with_capacity(VAD_FRAME);
+        let mut detector = BargeInDetector::default();
+
+        while playback.is_playing() {
+            // Wait a little at a time, so we notice when playback stops
+            let sample = match self.audio_receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(sample) => sample,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            if pre_roll.len() == PRE_ROLL {
+                pre_roll.pop_front();
+            }
+            pre_roll.push_back(sample);
+
+            frame.push(sample);
+            if frame.len() < VAD_FRAME {
+                continue;
+            }
+
+            let barged_in = detector.push_frame(&frame, playback.level());
+            frame.clear();
+
+            if barged_in {
+                println!("Audio Player : User barged in");
+                if let Some(events) = &self.events {
+                    events.do_send(Lifecycle::UserInterrupted);
+                }
+                self.llm.do_send(Cancel);
+
+                // Keep going until they're done talking
+                self.audio_data.extend(pre_roll.drain(..));
+                self.run_voice_activity_detection();
+                self.stream.pause().expect("Failed to pause stream");
+                return true;
+            }
+        }
+
+        self.stream.pause().expect("Failed to pause stream");
+
+        // Don't leave the assistant's voice lying around for the next recording
+        while self.audio_receiver.try_recv().is_ok() {}
+        false
+    }
 }
@@ -1,246 +1,273 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
 use actix::prelude::*;
 use anyhow::Result;
 
 use crate::{
-    audio_player::{Status, StatusRequest},
+    audio_player::{Cancel, Status, StatusRequest},
+    citation::{self, Citations},
+    conversation::Lifecycle,
+    fence_parser::{Event, FenceParser, ParseError, Segment},
+    interpreter::{Interpreter, Text},
+    sentence,
+    tts_polly::TtsPollyActor,
     tts_polly::Utterance,
-    tts_polly::TtsPollyActor, interpreter::{Interpreter, Text},
 };
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Token(pub String);
 
+/// Sent once the LLM has finished streaming a reply.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Flush;
+
+/// A recoverable parse failure, reported to the error sink.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct ParseFailure(pub ParseError);
+
+type Route = Box<dyn Fn(String)>;
+
 pub struct TokenProcessorActor {
-    token_buffer: Vec<String>,
-    tts: Addr<TtsPollyActor>,
-    idle: bool,
+    routes: HashMap<String, Route>,
+    parser: FenceParser,
+    error_sink: Option<Recipient<ParseFailure>>,
+
+    /// Languages whose blocks are sent sentence by sentence instead of as a whole
+    streamed: HashSet<String>,
+    /// How much of the open block has already been sent
+    sent: usize,
+    /// When the last token or flush came in
+    last_activity: Instant,
+
+    /// Passed on any `Cancel`, eg. so TTS drops what it has queued
+    downstream: Vec<Recipient<Cancel>>,
 
-    state: ProcState,
-    data_buffer: Vec<char>,
+    events: Option<Recipient<Lifecycle>>,
+    /// What to report when something is routed for each language
+    announcements: HashMap<String, Lifecycle>,
 
-    interpreter: Addr<Interpreter>,
+    /// Languages to take citation markers out of, and where to look them up
+    cited: HashMap<String, Citations>,
 }
 
 impl Actor for TokenProcessorActor {
     type Context = Context<Self>;
 }
 
-impl TokenProcessorActor {
-    pub fn with(tts: Addr<TtsPollyActor>, interpreter: Addr<Interpreter>) -> Self {
+impl Default for TokenProcessorActor {
+    fn default() -> Self {
         Self {
-            token_buffer: vec![],
-            tts,
-            idle: true,
-            state: ProcState::NotParsing,
-            data_buffer: Vec::with_capacity(1024),
-            interpreter,
+            routes: HashMap::new(),
+            parser: FenceParser::default(),
+            error_sink: None,
+            streamed: HashSet::new(),
+            sent: 0,
+            last_activity: Instant::now(),
+            downstream: vec![],
+            events: None,
+            announcements: HashMap::new(),
+            cited: HashMap::new(),
         }
     }
 }
 
-impl Handler<Token> for TokenProcessorActor {
-    type Result = ();
+impl TokenProcessorActor {
+    /// The usual setup: ```speech blocks are spoken and ```json blocks are interpreted.
+    pub fn with(tts: Addr<TtsPollyActor>, interpreter: Addr<Interpreter>) -> Self {
+        Self::default()
+            .route("speech", tts.clone().recipient::<Utterance>())
+            .route("json", interpreter.recipient::<Text>())
+            .cancels(tts.recipient::<Cancel>())
+    }
 
-    fn handle(&mut self, msg: Token, _ctx: &mut Context<Self>) -> Self::Result {
-        println!("Token Proc   : Received token {}", msg.0);
-        self.idle = false;
-
-        let token = msg.0;
-
-        // Find the next state and next thing to do
-        let (next_state, next_action) = transition(self.state, self.data_buffer.as_slice(), &token);
-        
-        // Execute the next thing to do
-        match next_action {
-            Event::DoNothing => {}
-            Event::Push { dst, pop } => {
-                let data: String = self.data_buffer.drain(..(self.data_buffer.len() - pop)).collect();
-                self.data_buffer.clear();
-                match dst {
-                    Dst::Speech => {
-                        self.tts.do_send(Utterance(data))
-                    },
-                    Dst::Actions => {
-                        self.interpreter.do_send(Text(data))
-                    },
+    /// Send the content of every block fenced with `lang` to `recipient`.
+    /// Registering the same language twice replaces the earlier route.
+    pub fn route<M>(mut self, lang: &str, recipient: Recipient<M>) -> Self
+    where
+        M: Message + From<String> + Send + 'static,
+        M::Result: Send,
+    {
+        self.routes.insert(
+            lang.to_string(),
+            Box::new(move |content: String| recipient.do_send(M::from(content))),
+        );
+        self
+    }
+
+    /// Send each completed sentence of `lang` blocks to its route as soon as it
+    /// arrives, instead of waiting for the closing fence.
+    pub fn stream_sentences(mut self, lang: &str) -> Self {
+        self.streamed.insert(lang.to_string());
+        self
+    }
+
+    /// Pass cancellations on to `recipient`.
+    pub fn cancels(mut self, recipient: Recipient<Cancel>) -> Self {
+        self.downstream.push(recipient);
+        self
+    }
+
+    /// Report to `events` whenever something is routed, eg. speech as `UtteranceQueued`
+    /// and actions as `ActionsStarted`. See `announce`.
+    pub fn events(mut self, events: Recipient<Lifecycle>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Report `event` for every block, or sentence, routed for `lang`.
+    pub fn announce(mut self, lang: &str, event: Lifecycle) -> Self {
+        self.announcements.insert(lang.to_string(), event);
+        self
+    }
+
+    /// Take markers like "[c1f3a9b2e]" out of blocks in `lang` before they're routed,
+    /// and print the sources they refer to.
+    pub fn citations(mut self, lang: &str, citations: Citations) -> Self {
+        self.cited.insert(lang.to_string(), citations);
+        self
+    }
+
+    fn send(&self, lang: &str, mut content: String) {
+        let Some(route) = self.routes.get(lang) else {
+            println!("Token Proc   : No route for {:?} block", lang);
+            return;
+        };
+
+        if let Some(citations) = self.cited.get(lang) {
+            let (stripped, ids) = citation::strip_markers(&content);
+            for id in ids {
+                match citations.resolve(&id) {
+                    Some(source) => println!("Sources      : [{}] {}", id, source),
+                    None => println!("Sources      : [{}] Unknown citation", id),
                 }
             }
-            Event::AddToBuffer => {
-                self.data_buffer.extend(token.chars());
+            content = stripped;
+
+            // A marker can end up in a sentence of its own, eg. "It's blue. [c1f3a9b2e]"
+            if content.trim().is_empty() {
+                return;
             }
         }
-            
-        // Update state
-        self.state = next_state;
-        self.idle = true;
 
-        // println!("{:?}", self.state);
+        if let (Some(events), Some(event)) = (&self.events, self.announcements.get(lang)) {
+            events.do_send(*event);
+        }
+        route(content);
     }
-}
 
-impl Handler<StatusRequest> for TokenProcessorActor {
-    type Result = Result<Status>;
+    /// Send parse failures somewhere other than stderr.
+    pub fn error_sink(mut self, error_sink: Recipient<ParseFailure>) -> Self {
+        self.error_sink = Some(error_sink);
+        self
+    }
 
-    fn handle(&mut self, _msg: StatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
-        // println!("Token Proc  : Received status request");
+    fn dispatch(&mut self, events: Vec<Event>) {
+        for event in events {
+            match event {
+                Event::Segment(Segment::Block { lang, content }) => {
+                    // Only the first block can have been partly sent already
+                    let sent = std::mem::take(&mut self.sent);
 
-        if self.idle && self.token_buffer.is_empty() && self.data_buffer.is_empty() {
-            Ok(Status::Idle)
-        } else {
-            Ok(Status::Busy)
+                    if self.streamed.contains(&lang) {
+                        let rest = content[sent..].trim();
+                        if !rest.is_empty() {
+                            self.send(&lang, rest.to_string());
+                        }
+                    } else {
+                        self.send(&lang, content);
+                    }
+                }
+                Event::Segment(Segment::Text(text)) => {
+                    println!("Token Proc   : Ignoring text outside block {:?}", text.trim())
+                }
+                Event::Error(error) => match &self.error_sink {
+                    Some(error_sink) => error_sink.do_send(ParseFailure(error)),
+                    None => eprintln!("Token Proc   : {}", error),
+                },
+            }
         }
     }
-}
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum ProcState {
-    Parsing(ParseState),
-    NotParsing,
-}
+    /// Send whatever sentences have been completed in the open block so far.
+    fn dispatch_sentences(&mut self) {
+        let Some((lang, content)) = self.parser.open_block() else {
+            return;
+        };
+        if !self.streamed.contains(lang) {
+            return;
+        }
+        if !self.routes.contains_key(lang) {
+            return;
+        }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum ParseState {
-    Speech,
-    Actions,
-    Unknown { num_backticks: u8 },
+        let (sentences, end) = sentence::complete_sentences(&content[self.sent..]);
+        for sentence in sentences {
+            self.send(lang, sentence.to_string());
+        }
+        self.sent += end;
+    }
 }
 
-enum Event {
-    DoNothing,
-    AddToBuffer,
-    Push { dst: Dst, pop: usize },
+impl Handler<Token> for TokenProcessorActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Token, _ctx: &mut Context<Self>) -> Self::Result {
+        println!("Token Proc   : Received token {}", msg.0);
+        self.last_activity = Instant::now();
+
+        let events = self.parser.push(&msg.0);
+        self.dispatch(events);
+        self.dispatch_sentences();
+    }
 }
 
-enum Dst {
-    Speech,
-    Actions,
+impl Handler<Flush> for TokenProcessorActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Context<Self>) -> Self::Result {
+        self.last_activity = Instant::now();
+        let events = self.parser.finish();
+        self.dispatch(events);
+        self.sent = 0;
+    }
 }
 
-fn transition(
-    current_state: ProcState,
-    current_buffer: &[char],
-    token: &str,
-) -> (ProcState, Event) {
-    use Event::*;
-    use ParseState::*;
-    use ProcState::*;
-
-    // println!("Token: {}", token);
-
-    match (current_state, current_buffer, token) {
-        // Not parsing yet
-        (NotParsing, _, "`") => (Parsing(Unknown { num_backticks: 1 }), DoNothing),
-        (NotParsing, _, "``") => (Parsing(Unknown { num_backticks: 2 }), DoNothing),
-        (NotParsing, _, "```") => (Parsing(Unknown { num_backticks: 3 }), DoNothing),
-        (NotParsing, _, other) => {
-            if other == "```speech" {
-                (Parsing(Speech), DoNothing)
-            } else if other == "```json" {
-                (Parsing(Actions), DoNothing)
-            } else if other.starts_with("```speech") {
-                // TODO: Incorrect
-                (Parsing(Speech), DoNothing)
-            } else if other.starts_with("```json") {
-                // TODO: Incorrect
-                (Parsing(Actions), DoNothing)
-            } else {
-                panic!("Invalid state. Token: {}", token);
-            }
-        }
+impl Handler<Cancel> for TokenProcessorActor {
+    type Result = ();
 
-        // 1 backtick
-        (Parsing(Unknown { num_backticks: 1 }), _, "``") => {
-            (Parsing(Unknown { num_backticks: 3 }), DoNothing)
-        }
-        (Parsing(Unknown { num_backticks: 1 }), _, "``speech") => (Parsing(Speech), DoNothing),
-        (Parsing(Unknown { num_backticks: 1 }), _, "``json") => (Parsing(Actions), DoNothing),
+    fn handle(&mut self, msg: Cancel, _ctx: &mut Context<Self>) -> Self::Result {
+        println!("Token Proc   : Cancelled");
+
+        // Throw away the half-parsed reply
+        self.parser = FenceParser::default();
+        self.sent = 0;
 
-        // 2 backticks
-        (Parsing(Unknown { num_backticks: 2 }), _, "`") => {
-            (Parsing(Unknown { num_backticks: 3 }), DoNothing)
+        for recipient in &self.downstream {
+            recipient.do_send(msg);
         }
-        (Parsing(Unknown { num_backticks: 2 }), _, "`speech") => (Parsing(Speech), DoNothing),
-        (Parsing(Unknown { num_backticks: 2 }), _, "`json") => (Parsing(Actions), DoNothing),
+    }
+}
 
-        // 3 backticks
-        (Parsing(Unknown { num_backticks: 3 }), _, "speech") => (Parsing(Speech), DoNothing),
-        (Parsing(Unknown { num_backticks: 3 }), _, "json") => (Parsing(Actions), DoNothing),
+impl Handler<StatusRequest> for TokenProcessorActor {
+    type Result = Result<Status>;
 
-        // Other backticks
-        (Parsing(Unknown { num_backticks }), _, token) => {
-            panic!("Invalid {} {}", num_backticks, token)
-        }
+    fn handle(&mut self, _msg: StatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        // println!("Token Proc  : Received status request");
 
-        // While parsing
-        (Parsing(parse_state), _, "```") => match parse_state {
-            Speech => (
-                NotParsing,
-                Push {
-                    dst: Dst::Speech,
-                    pop: 0,
-                },
-            ),
-            Actions => (
-                NotParsing,
-                Push {
-                    dst: Dst::Actions,
-                    pop: 0,
-                },
-            ),
-            Unknown { num_backticks: _ } => panic!(),
-        },
-        (Parsing(parse_state), current_buffer, "``" | "``\n" | "``\n\n") => {
-            if current_buffer
-                .last()
-                .expect("current_buffer should not be empty")
-                == &'`'
-            {
-                match parse_state {
-                    Speech => (
-                        NotParsing,
-                        Push {
-                            dst: Dst::Speech,
-                            pop: 1,
-                        },
-                    ),
-                    Actions => (
-                        NotParsing,
-                        Push {
-                            dst: Dst::Actions,
-                            pop: 1,
-                        },
-                    ),
-                    Unknown { num_backticks: _ } => panic!(),
-                }
-            } else {
-                (Parsing(parse_state), AddToBuffer)
-            }
-        }
-        (Parsing(parse_state), current_buffer, "`" | "`\n" | "`\n\n") => {
-            let last_two: String = current_buffer.iter().rev().take(2).collect();
-            if last_two == "``" {
-                match parse_state {
-                    Speech => (
-                        NotParsing,
-                        Push {
-                            dst: Dst::Speech,
-                            pop: 2,
-                        },
-                    ),
-                    Actions => (
-                        NotParsing,
-                        Push {
-                            dst: Dst::Actions,
-                            pop: 2,
-                        },
-                    ),
-                    Unknown { num_backticks: _ } => panic!(),
-                }
-            } else {
-                (Parsing(parse_state), AddToBuffer)
-            }
-        }
-        (Parsing(parse_state), _, _) => (Parsing(parse_state), AddToBuffer),
+        let current_job = self
+            .parser
+            .open_block()
+            .map(|(lang, _)| format!("Parsing a {} block", lang));
+        Ok(Status {
+            queue_depth: usize::from(!self.parser.is_idle()),
+            current_job,
+            since_last_activity: self.last_activity.elapsed(),
+        })
     }
 }
@@ -1,19 +1,25 @@
 use std::env;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use actix::prelude::*;
 use reqwest::Client;
 use serde::Serialize;
 
-use crate::audio_player::{Audio, AudioPlayerActor, Status, StatusRequest};
+use crate::audio_player::{Activity, Audio, AudioPlayerActor, Status, StatusRequest};
 
 #[derive(Message)]
 #[rtype(result = "Result<()>")]
 pub struct Utterance(pub String);
 
+impl From<String> for Utterance {
+    fn from(text: String) -> Self {
+        Self(text)
+    }
+}
+
 pub struct TtsActor {
     client: Client,
     audio_player: Addr<AudioPlayerActor>,
-    idle: bool,
+    activity: Activity,
 }
 
 impl Actor for TtsActor {
@@ -35,17 +41,16 @@ struct TextToSpeechRequest<'a> {
 impl TtsActor {
     pub fn with(audio_player: Addr<AudioPlayerActor>) -> Self {
         let client = Client::new();
-        let idle = true;
         Self {
             audio_player,
             client,
-            idle,
+            activity: Activity::default(),
         }
     }
 }
 
 impl Handler<Utterance> for TtsActor {
-    type Result = ResponseFuture<Result<()>>;
+    type Result = ResponseActFuture<Self, Result<()>>;
 
     fn handle(&mut self, msg: Utterance, _: &mut Context<Self>) -> Self::Result {
         println!("TTS         : Received {}", msg.0);
@@ -54,12 +59,13 @@ impl Handler<Utterance> for TtsActor {
         let audio_player = self.audio_player.clone();
         let client = self.client.clone(); // Client is already ARC-wrapped
 
-        self.idle = false;
+        let job = self.activity.start(format!("Synthesising {:?}", text));
 
-        let b = Box::pin(async move {
+        let b = async move {
             let api_url = "https://api.elevenlabs.io/v1/text-to-speech/";
             let voice_id = "EXAVITQu4vr4xnSDxMaL";
-            let api_key = env::var("ELEVENLABS_API_KEY").expect("ELEVENLABS_API_KEY must be set");
+            let api_key =
+                env::var("ELEVENLABS_API_KEY").map_err(|_| anyhow!("ELEVENLABS_API_KEY must be set"))?;
             let url = format!("{}{}", api_url, voice_id);
 
             let voice_settings = VoiceSettings {
@@ -75,20 +81,21 @@ impl Handler<Utterance> for TtsActor {
                 .header("xi-api-key", api_key)
                 .json(&request_body)
                 .send()
-                .await
-                .unwrap();
+                .await?
+                .error_for_status()?;
 
-            let data = response.bytes().await.unwrap().to_vec();
+            let data = response.bytes().await?.to_vec();
 
             // Ensures that messages are in order
-            let _ = audio_player.send(Audio(data)).await.unwrap();
+            audio_player.send(Audio(data)).await??;
 
-            Ok(())
-        });
+            Ok::<_, anyhow::Error>(())
+        };
 
-        self.idle = true;
-
-        b
+        Box::pin(b.into_actor(self).map(move |result, act, _ctx| {
+            act.activity.finish(job);
+            result
+        }))
     }
 }
 
@@ -98,10 +105,6 @@ impl Handler<StatusRequest> for TtsActor {
     fn handle(&mut self, _msg: StatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
         println!("TTS         : Received status request");
 
-        if self.idle {
-            Ok(Status::Idle)
-        } else {
-            Ok(Status::Busy)
-        }
+        Ok(self.activity.status())
     }
 }
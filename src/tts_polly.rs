@@ -1,3 +1,8 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
 use actix::prelude::*;
 use aws_sdk_polly as polly;
 use polly::{
@@ -5,17 +10,32 @@ use polly::{
     Client,
 };
 use anyhow::Result;
+use tokio::sync::oneshot;
 
-use crate::audio_player::{Audio, AudioPlayerActor, Status, StatusRequest};
+use crate::{
+    audio_player::{Activity, Audio, AudioPlayerActor, Cancel, Status, StatusRequest},
+    conversation::Lifecycle,
+};
 
 #[derive(Message)]
 #[rtype(result = "Result<()>")]
 pub struct Utterance(pub String);
 
+impl From<String> for Utterance {
+    fn from(text: String) -> Self {
+        Self(text)
+    }
+}
+
 pub struct TtsPollyActor {
     audio_player: Addr<AudioPlayerActor>,
     client: Client,
-    idle: bool,
+    activity: Activity,
+    /// Resolves once the previous utterance has been handed to the audio player
+    previous: Option<oneshot::Receiver<()>>,
+    /// Bumped on every cancel, so that utterances from before it are dropped
+    generation: Arc<AtomicUsize>,
+    events: Option<Recipient<Lifecycle>>,
 }
 
 impl Actor for TtsPollyActor {
@@ -26,17 +46,25 @@ impl TtsPollyActor {
     pub async fn with(audio_player: Addr<AudioPlayerActor>) -> Self {
         let config = aws_config::load_from_env().await;
         let client = polly::Client::new(&config);
-        let idle = true;
         Self {
             audio_player,
             client,
-            idle,
+            activity: Activity::default(),
+            previous: None,
+            generation: Arc::new(AtomicUsize::new(0)),
+            events: None,
         }
     }
+
+    /// Report utterances that get dropped by a cancel or an error, as they'll never be played.
+    pub fn events(mut self, events: Recipient<Lifecycle>) -> Self {
+        self.events = Some(events);
+        self
+    }
 }
 
 impl Handler<Utterance> for TtsPollyActor {
-    type Result = ResponseFuture<Result<()>>;
+    type Result = ResponseActFuture<Self, Result<()>>;
 
     fn handle(&mut self, msg: Utterance, _ctx: &mut Self::Context) -> Self::Result {
         println!("TTS          : Received {}", msg.0.trim());
@@ -44,29 +72,79 @@ impl Handler<Utterance> for TtsPollyActor {
         let audio_player = self.audio_player.clone();
         let client = self.client.clone();
 
-        self.idle = false;
-
-        let b = Box::pin(async move {
-            let resp = client
-                .synthesize_speech()
-                .engine(Engine::Standard)
-                .voice_id(polly::types::VoiceId::Matthew)
-                .output_format(OutputFormat::Mp3)
-                .text(msg.0.trim())
-                .send()
-                .await
-                .unwrap();
-
-            let data = resp.audio_stream.collect().await.unwrap().to_vec();
-
-            let _ = audio_player.send(Audio(data)).await.unwrap();
+        let job = self.activity.start(format!("Synthesising {:?}", msg.0.trim()));
+
+        // Synthesis can run concurrently but playback must keep the order
+        let previous = self.previous.take();
+        let (done, next) = oneshot::channel();
+        self.previous = Some(next);
+
+        let generation = self.generation.clone();
+        let started = generation.load(Ordering::SeqCst);
+        let events = self.events.clone();
+
+        let b = async move {
+            let text = msg.0.trim();
+            let synthesised = synthesise(&client, text).await;
+
+            // Wait for our turn
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+
+            let queued = match synthesised {
+                Ok(_) if generation.load(Ordering::SeqCst) != started => {
+                    println!("TTS          : Dropped cancelled utterance");
+                    Ok(false)
+                }
+                Ok(data) => match audio_player.send(Audio(data)).await {
+                    Ok(result) => result.map(|_| true),
+                    Err(e) => Err(e.into()),
+                },
+                Err(e) => Err(e),
+            };
+            let _ = done.send(());
+
+            // The audio player only reports what it actually got to play
+            if !matches!(queued, Ok(true)) {
+                if let Some(events) = events {
+                    events.do_send(Lifecycle::UtterancePlayed);
+                }
+            }
+            if let Err(e) = &queued {
+                eprintln!("TTS          : Failed to speak {:?}: {}", text, e);
+            }
+
+            queued.map(|_| ())
+        };
+
+        Box::pin(b.into_actor(self).map(move |result, act, _ctx| {
+            act.activity.finish(job);
+            result
+        }))
+    }
+}
 
-            Ok(())
-        });
+async fn synthesise(client: &Client, text: &str) -> Result<Vec<u8>> {
+    let resp = client
+        .synthesize_speech()
+        .engine(Engine::Standard)
+        .voice_id(polly::types::VoiceId::Matthew)
+        .output_format(OutputFormat::Mp3)
+        .text(text)
+        .send()
+        .await?;
+
+    Ok(resp.audio_stream.collect().await?.to_vec())
+}
 
-        self.idle = true;
+impl Handler<Cancel> for TtsPollyActor {
+    type Result = ();
 
-        b
+    fn handle(&mut self, msg: Cancel, _ctx: &mut Self::Context) -> Self::Result {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.previous = None;
+        self.audio_player.do_send(msg);
     }
 }
 
@@ -76,10 +154,6 @@ impl Handler<StatusRequest> for TtsPollyActor {
     fn handle(&mut self, _msg: StatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
         // println!("TTS         : Received status request");
 
-        if self.idle {
-            Ok(Status::Idle)
-        } else {
-            Ok(Status::Busy)
-        }
+        Ok(self.activity.status())
     }
 }
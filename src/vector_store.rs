@@ -0,0 +1,487 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use actix::prelude::*;
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The payload field that holds the text a point was embedded from.
+pub const TEXT_FIELD: &str = "text";
+
+pub type Payload = HashMap<String, serde_json::Value>;
+
+/// A vector and what's stored alongside it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub id: u64,
+    pub vector: Vec<f32>,
+    pub payload: Payload,
+}
+
+/// How closeness between vectors is measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    /// The angle between them, ignoring length. What the embedding model is made for.
+    #[default]
+    Cosine,
+    Dot,
+    /// Straight-line distance, where lower scores are closer
+    Euclid,
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Distance::Cosine => write!(f, "cosine"),
+            Distance::Dot => write!(f, "dot"),
+            Distance::Euclid => write!(f, "euclid"),
+        }
+    }
+}
+
+/// What a store knows about one of its collections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectionDetails {
+    pub name: String,
+    pub points: u64,
+    pub dimension: u64,
+    pub distance: Distance,
+}
+
+impl fmt::Display for CollectionDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} has {} points with {} dimensional vectors, compared by {} distance.",
+            self.name, self.points, self.dimension, self.distance
+        )
+    }
+}
+
+/// Bounds on a number. Unset bounds don't apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Range {
+    pub gt: Option<f64>,
+    pub gte: Option<f64>,
+    pub lt: Option<f64>,
+    pub lte: Option<f64>,
+}
+
+impl Range {
+    fn contains(&self, value: f64) -> bool {
+        self.gt.map_or(true, |gt| value > gt)
+            && self.gte.map_or(true, |gte| value >= gte)
+            && self.lt.map_or(true, |lt| value < lt)
+            && self.lte.map_or(true, |lte| value <= lte)
+    }
+}
+
+/// A test on one payload field. A field holding a list passes if any item does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The field is exactly this string or bool, or equal to this number, so 3
+    /// matches 3.0. Nothing else can be matched.
+    Match { field: String, value: serde_json::Value },
+    /// The field is a number within the range
+    Range { field: String, range: Range },
+}
+
+impl Condition {
+    /// The field is exactly this string.
+    pub fn keyword(field: &str, value: &str) -> Self {
+        Condition::Match {
+            field: field.to_string(),
+            value: value.into(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        match self {
+            Condition::Match { field, .. } | Condition::Range { field, .. } => field,
+        }
+    }
+
+    pub fn matches(&self, payload: &Payload) -> bool {
+        let values = match payload.get(self.field()) {
+            Some(serde_json::Value::Array(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+            None => vec![],
+        };
+        values.into_iter().any(|value| match self {
+            Condition::Match { value: wanted, .. } => match wanted {
+                serde_json::Value::String(_) | serde_json::Value::Bool(_) => value == wanted,
+                serde_json::Value::Number(wanted) => value.as_f64() == wanted.as_f64(),
+                _ => false,
+            },
+            Condition::Range { range, .. } => value.as_f64().map_or(false, |value| range.contains(value)),
+        })
+    }
+}
+
+/// Which points to search or delete: those matching every `must` condition, at
+/// least one `should` condition if there are any, and no `must_not` condition.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    pub must: Vec<Condition>,
+    pub should: Vec<Condition>,
+    pub must_not: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn must(mut self, condition: Condition) -> Self {
+        self.must.push(condition);
+        self
+    }
+
+    pub fn should(mut self, condition: Condition) -> Self {
+        self.should.push(condition);
+        self
+    }
+
+    pub fn must_not(mut self, condition: Condition) -> Self {
+        self.must_not.push(condition);
+        self
+    }
+
+    /// Points whose `field` is any of `values`.
+    pub fn any_of(field: &str, values: &[String]) -> Self {
+        Self {
+            should: values.iter().map(|value| Condition::keyword(field, value)).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, payload: &Payload) -> bool {
+        self.must.iter().all(|condition| condition.matches(payload))
+            && (self.should.is_empty() || self.should.iter().any(|condition| condition.matches(payload)))
+            && !self.must_not.iter().any(|condition| condition.matches(payload))
+    }
+}
+
+/// Names end up in file paths for the stores on local disk, so there can't be
+/// a collection called "../x". Letters, digits, '_' and '-' only.
+pub fn valid_collection_name(collection_name: &str) -> bool {
+    !collection_name.is_empty()
+        && collection_name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+/// A point that matched a search, best first.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub text: String,
+    pub score: f32,
+    /// The rest of the payload, eg. where the text came from
+    pub payload: Payload,
+}
+
+impl SearchHit {
+    /// Points without any text are no use to the LLM.
+    pub fn from_payload(mut payload: Payload, score: f32) -> Option<Self> {
+        let text = match payload.remove(TEXT_FIELD)? {
+            serde_json::Value::String(text) => text,
+            _ => return None,
+        };
+        Some(Self { text, score, payload })
+    }
+}
+
+/// Anywhere vectors can be stored and searched by similarity.
+pub trait VectorStore: Send + Sync {
+    /// Creates the collection if it isn't there yet.
+    fn create_collection(
+        &self,
+        collection_name: String,
+        dimension: u64,
+        distance: Distance,
+    ) -> BoxFuture<'static, Result<()>>;
+
+    fn list_collections(&self) -> BoxFuture<'static, Result<Vec<String>>>;
+
+    /// `None` if there's no such collection.
+    fn collection_info(&self, collection_name: String) -> BoxFuture<'static, Result<Option<CollectionDetails>>>;
+
+    /// Drops the collection and all its points. Returns whether it was there.
+    fn delete_collection(&self, collection_name: String) -> BoxFuture<'static, Result<bool>>;
+
+    /// Inserts points, replacing any that have the same ids.
+    fn upsert(&self, collection_name: String, points: Vec<Point>) -> BoxFuture<'static, Result<()>>;
+
+    fn delete(&self, collection_name: String, filter: Filter) -> BoxFuture<'static, Result<()>>;
+
+    fn search(&self, request: SearchRequest) -> BoxFuture<'static, Result<Vec<SearchHit>>>;
+
+    /// The payloads of every point, with just `fields`.
+    /// A collection that doesn't exist yet is treated as empty.
+    fn scroll(&self, collection_name: String, fields: Vec<String>) -> BoxFuture<'static, Result<Vec<Payload>>>;
+
+    /// Saves whatever changed since the last flush. Stores that save as they go
+    /// have nothing to do.
+    fn flush(&self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The points nearest to `vector`, best first.
+#[derive(Message, Debug, Clone, Default)]
+#[rtype(result = "Result<Vec<SearchHit>>")]
+pub struct SearchRequest {
+    pub collection_name: String,
+    pub vector: Vec<f32>,
+    /// How many of the nearest points to return
+    pub limit: u64,
+    /// Leave out points less similar than this
+    pub score_threshold: Option<f32>,
+    /// Payload fields to return, or all of them if `None`. The text always comes back.
+    pub fields: Option<Vec<String>>,
+    /// Only search among the points that match
+    pub filter: Option<Filter>,
+}
+
+impl SearchRequest {
+    /// Whether a hit should carry `field`.
+    pub fn wants(&self, field: &str) -> bool {
+        field == TEXT_FIELD
+            || self
+                .fields
+                .as_ref()
+                .map_or(true, |fields| fields.iter().any(|wanted| wanted == field))
+    }
+}
+
+/// Creates a new, empty collection. Fails if there's one by that name already.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct CreateCollection {
+    pub collection_name: String,
+    pub dimension: u64,
+    pub distance: Distance,
+}
+
+/// Names of every collection, sorted.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<String>>")]
+pub struct ListCollections;
+
+#[derive(Message)]
+#[rtype(result = "Result<CollectionDetails>")]
+pub struct CollectionInfo {
+    pub collection_name: String,
+}
+
+/// Drops a collection and all its points.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct DeleteCollection {
+    pub collection_name: String,
+}
+
+/// Creates the collection for cosine similarity if it isn't there yet, and fails
+/// if it's there but compares vectors some other way.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct EnsureCollection {
+    pub collection_name: String,
+    /// Length of the vectors that will go in it
+    pub dimension: u64,
+}
+
+/// Inserts points, replacing any that have the same ids.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct Upsert {
+    pub collection_name: String,
+    pub points: Vec<Point>,
+}
+
+/// The payloads of every point in a collection, with just the fields asked for.
+/// A collection that doesn't exist yet is treated as empty.
+#[derive(Message)]
+#[rtype(result = "Result<Vec<Payload>>")]
+pub struct Scroll {
+    pub collection_name: String,
+    pub fields: Vec<String>,
+}
+
+/// Deletes every point whose `field` is one of `values`.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct DeleteWhere {
+    pub collection_name: String,
+    pub field: String,
+    pub values: Vec<String>,
+}
+
+/// Deletes every point the filter matches.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct DeleteMatching {
+    pub collection_name: String,
+    pub filter: Filter,
+}
+
+/// Save every change the store is holding, eg. at the end of indexing, rather
+/// than rewriting its files after every batch.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct Flush;
+
+/// Serves the messages above from whichever store it's given.
+pub struct VectorDb {
+    store: Arc<dyn VectorStore>,
+}
+
+impl Actor for VectorDb {
+    type Context = Context<Self>;
+}
+
+impl VectorDb {
+    pub fn with(store: impl VectorStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+}
+
+impl Handler<SearchRequest> for VectorDb {
+    type Result = ResponseFuture<Result<Vec<SearchHit>>>;
+
+    fn handle(&mut self, msg: SearchRequest, _ctx: &mut Self::Context) -> Self::Result {
+        self.store.search(msg)
+    }
+}
+
+impl Handler<EnsureCollection> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: EnsureCollection, _ctx: &mut Self::Context) -> Self::Result {
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            if let Some(details) = store.collection_info(msg.collection_name.clone()).await? {
+                if details.distance != Distance::Cosine {
+                    bail!(
+                        "{} compares vectors by {} distance, but documents need cosine",
+                        msg.collection_name,
+                        details.distance
+                    );
+                }
+                return Ok(());
+            }
+            store
+                .create_collection(msg.collection_name, msg.dimension, Distance::Cosine)
+                .await
+        })
+    }
+}
+
+impl Handler<CreateCollection> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: CreateCollection, _ctx: &mut Self::Context) -> Self::Result {
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            if msg.dimension == 0 {
+                bail!("Vectors need at least one dimension");
+            }
+            if store.collection_info(msg.collection_name.clone()).await?.is_some() {
+                bail!("There's already a collection named {}", msg.collection_name);
+            }
+            store
+                .create_collection(msg.collection_name, msg.dimension, msg.distance)
+                .await
+        })
+    }
+}
+
+impl Handler<ListCollections> for VectorDb {
+    type Result = ResponseFuture<Result<Vec<String>>>;
+
+    fn handle(&mut self, _msg: ListCollections, _ctx: &mut Self::Context) -> Self::Result {
+        let names = self.store.list_collections();
+
+        Box::pin(async move {
+            let mut names = names.await?;
+            names.sort();
+            Ok(names)
+        })
+    }
+}
+
+impl Handler<CollectionInfo> for VectorDb {
+    type Result = ResponseFuture<Result<CollectionDetails>>;
+
+    fn handle(&mut self, msg: CollectionInfo, _ctx: &mut Self::Context) -> Self::Result {
+        let details = self.store.collection_info(msg.collection_name.clone());
+
+        Box::pin(async move {
+            match details.await? {
+                Some(details) => Ok(details),
+                None => bail!("There's no collection named {}", msg.collection_name),
+            }
+        })
+    }
+}
+
+impl Handler<DeleteCollection> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: DeleteCollection, _ctx: &mut Self::Context) -> Self::Result {
+        let deleted = self.store.delete_collection(msg.collection_name.clone());
+
+        Box::pin(async move {
+            if !deleted.await? {
+                bail!("There's no collection named {}", msg.collection_name);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Handler<Upsert> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: Upsert, _ctx: &mut Self::Context) -> Self::Result {
+        self.store.upsert(msg.collection_name, msg.points)
+    }
+}
+
+impl Handler<Scroll> for VectorDb {
+    type Result = ResponseFuture<Result<Vec<Payload>>>;
+
+    fn handle(&mut self, msg: Scroll, _ctx: &mut Self::Context) -> Self::Result {
+        self.store.scroll(msg.collection_name, msg.fields)
+    }
+}
+
+impl Handler<DeleteWhere> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: DeleteWhere, _ctx: &mut Self::Context) -> Self::Result {
+        if msg.values.is_empty() {
+            return Box::pin(async { Ok(()) });
+        }
+        self.store
+            .delete(msg.collection_name, Filter::any_of(&msg.field, &msg.values))
+    }
+}
+
+impl Handler<DeleteMatching> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: DeleteMatching, _ctx: &mut Self::Context) -> Self::Result {
+        self.store.delete(msg.collection_name, msg.filter)
+    }
+}
+
+impl Handler<Flush> for VectorDb {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Self::Context) -> Self::Result {
+        self.store.flush()
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, ensure, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::vector_store::{
+    valid_collection_name, CollectionDetails, Distance, Filter, Payload, Point, SearchHit, SearchRequest,
+    VectorStore,
+};
+
+pub const VECTOR_DIR: &str = "vectors";
+
+/// What's saved to `<dir>/<collection>.json`.
+#[derive(Default, Serialize, Deserialize)]
+struct Collection {
+    dimension: u64,
+    #[serde(default)]
+    distance: Distance,
+    points: HashMap<u64, StoredPoint>,
+}
+
+impl Collection {
+    fn score(&self, query: &[f32], vector: &[f32]) -> f32 {
+        match self.distance {
+            Distance::Cosine | Distance::Dot => dot(query, vector),
+            Distance::Euclid => query
+                .iter()
+                .zip(vector)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+
+    /// Euclidean scores are distances, so lower is better, as with Qdrant.
+    fn lower_is_better(&self) -> bool {
+        self.distance == Distance::Euclid
+    }
+
+    fn prepare(&self, vector: Vec<f32>) -> Vec<f32> {
+        match self.distance {
+            Distance::Cosine => normalise(vector),
+            Distance::Dot | Distance::Euclid => vector,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredPoint {
+    /// Normalised when stored for cosine distance, so cosine similarity is a dot product
+    vector: Vec<f32>,
+    payload: Payload,
+}
+
+struct Collections {
+    dir: PathBuf,
+    loaded: HashMap<String, Collection>,
+    /// Changed since they were last saved
+    unsaved: HashSet<String>,
+}
+
+impl Collections {
+    fn path(&self, collection_name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", collection_name))
+    }
+
+    /// Loads the collection from disk the first time it's used.
+    fn get(&mut self, collection_name: &str) -> Result<Option<&mut Collection>> {
+        // Names end up in paths, so there can't be a collection called "../x"
+        if !valid_collection_name(collection_name) {
+            return Ok(None);
+        }
+        if !self.loaded.contains_key(collection_name) {
+            let path = self.path(collection_name);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let collection: Collection = serde_json::from_slice(&fs::read(&path)?)?;
+            println!("Local Store  : Loaded {} points from {}", collection.points.len(), path.display());
+            self.loaded.insert(collection_name.to_string(), collection);
+        }
+        Ok(self.loaded.get_mut(collection_name))
+    }
+
+    fn save(&self, collection_name: &str) -> Result<()> {
+        let Some(collection) = self.loaded.get(collection_name) else {
+            return Ok(());
+        };
+        // Write then rename, so a crash can't leave half a file behind
+        let path = self.path(collection_name);
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_vec(collection)?)?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Save every collection that changed since it was last saved.
+    fn flush(&mut self) -> Result<()> {
+        let mut unsaved: Vec<String> = self.unsaved.iter().cloned().collect();
+        unsaved.sort();
+        for collection_name in unsaved {
+            self.save(&collection_name)?;
+            self.unsaved.remove(&collection_name);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Collections {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Local Store  : Unable to save: {}", e);
+        }
+    }
+}
+
+/// Vectors kept in files on this machine, for when there's no Qdrant server.
+///
+/// Searches compare against every point in the collection, so it's meant for tens
+/// of thousands of chunks rather than millions. Changed collections are kept in
+/// memory and only written out on `flush`, eg. at the end of indexing, and when
+/// the last handle to the store is dropped. The work is done on the blocking
+/// thread pool so it doesn't hold up the actors.
+#[derive(Clone)]
+pub struct LocalStore {
+    collections: Arc<Mutex<Collections>>,
+}
+
+impl LocalStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            collections: Arc::new(Mutex::new(Collections {
+                dir: dir.as_ref().to_path_buf(),
+                loaded: HashMap::new(),
+                unsaved: HashSet::new(),
+            })),
+        })
+    }
+
+    fn create_collection_now(&self, collection_name: &str, dimension: u64, distance: Distance) -> Result<()> {
+        ensure!(
+            valid_collection_name(collection_name),
+            "Collection names can only have letters, digits, '_' and '-'"
+        );
+        let mut collections = self.collections.lock().unwrap();
+        if collections.get(collection_name)?.is_some() {
+            return Ok(());
+        }
+
+        println!(
+            "Local Store  : Creating collection {} with dimension {} and {} distance",
+            collection_name, dimension, distance
+        );
+        collections.loaded.insert(
+            collection_name.to_string(),
+            Collection {
+                dimension,
+                distance,
+                points: HashMap::new(),
+            },
+        );
+        collections.save(collection_name)
+    }
+
+    fn list_collections_now(&self) -> Result<Vec<String>> {
+        let collections = self.collections.lock().unwrap();
+        let mut names = vec![];
+        for entry in fs::read_dir(&collections.dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |extension| extension == "json") {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn collection_info_now(&self, collection_name: &str) -> Result<Option<CollectionDetails>> {
+        let mut collections = self.collections.lock().unwrap();
+        Ok(collections.get(collection_name)?.map(|collection| CollectionDetails {
+            name: collection_name.to_string(),
+            points: collection.points.len() as u64,
+            dimension: collection.dimension,
+            distance: collection.distance,
+        }))
+    }
+
+    fn delete_collection_now(&self, collection_name: &str) -> Result<bool> {
+        let mut collections = self.collections.lock().unwrap();
+        if collections.get(collection_name)?.is_none() {
+            return Ok(false);
+        }
+
+        println!("Local Store  : Deleting collection {}", collection_name);
+        collections.loaded.remove(collection_name);
+        collections.unsaved.remove(collection_name);
+        fs::remove_file(collections.path(collection_name))?;
+        Ok(true)
+    }
+
+    fn upsert_now(&self, collection_name: &str, points: Vec<Point>) -> Result<()> {
+        let mut collections = self.collections.lock().unwrap();
+        let collection = collections
+            .get(collection_name)?
+            .ok_or_else(|| anyhow!("No collection named {}", collection_name))?;
+
+        // Check the whole batch first, so a bad point can't leave half of it applied
+        if let Some(point) = points
+            .iter()
+            .find(|point| point.vector.len() as u64 != collection.dimension)
+        {
+            return Err(anyhow!(
+                "Vector {} has dimension {}, but {} expects {}",
+                point.id,
+                point.vector.len(),
+                collection_name,
+                collection.dimension
+            ));
+        }
+        for point in points {
+            let vector = collection.prepare(point.vector);
+            collection.points.insert(
+                point.id,
+                StoredPoint {
+                    vector,
+                    payload: point.payload,
+                },
+            );
+        }
+        collections.unsaved.insert(collection_name.to_string());
+        Ok(())
+    }
+
+    fn delete_now(&self, collection_name: &str, filter: &Filter) -> Result<()> {
+        let mut collections = self.collections.lock().unwrap();
+        let Some(collection) = collections.get(collection_name)? else {
+            return Ok(());
+        };
+        let before = collection.points.len();
+        collection.points.retain(|_, point| !filter.matches(&point.payload));
+        if collection.points.len() != before {
+            collections.unsaved.insert(collection_name.to_string());
+        }
+        Ok(())
+    }
+
+    fn search_now(&self, request: SearchRequest) -> Result<Vec<SearchHit>> {
+        let mut collections = self.collections.lock().unwrap();
+        let collection: &Collection = collections
+            .get(&request.collection_name)?
+            .ok_or_else(|| anyhow!("No collection named {}", request.collection_name))?;
+        ensure!(
+            request.vector.len() as u64 == collection.dimension,
+            "Query has dimension {}, but {} expects {}",
+            request.vector.len(),
+            request.collection_name,
+            collection.dimension
+        );
+
+        let query = collection.prepare(request.vector.clone());
+        let lower_is_better = collection.lower_is_better();
+        let mut scored: Vec<(f32, &StoredPoint)> = collection
+            .points
+            .values()
+            .filter(|point| {
+                request
+                    .filter
+                    .as_ref()
+                    .map_or(true, |filter| filter.matches(&point.payload))
+            })
+            .map(|point| (collection.score(&query, &point.vector), point))
+            .filter(|(score, _)| {
+                request.score_threshold.map_or(true, |threshold| {
+                    if lower_is_better {
+                        *score <= threshold
+                    } else {
+                        *score >= threshold
+                    }
+                })
+            })
+            .collect();
+        if lower_is_better {
+            scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+        } else {
+            scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        }
+
+        // Points without any text are no use to the LLM
+        Ok(scored
+            .into_iter()
+            .filter_map(|(score, point)| {
+                let payload = point
+                    .payload
+                    .iter()
+                    .filter(|(field, _)| request.wants(field))
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect();
+                SearchHit::from_payload(payload, score)
+            })
+            .take(request.limit as usize)
+            .collect())
+    }
+
+    fn scroll_now(&self, collection_name: &str, fields: &[String]) -> Result<Vec<Payload>> {
+        let mut collections = self.collections.lock().unwrap();
+        let Some(collection) = collections.get(collection_name)? else {
+            return Ok(vec![]);
+        };
+        Ok(collection
+            .points
+            .values()
+            .map(|point| {
+                point
+                    .payload
+                    .iter()
+                    .filter(|(field, _)| fields.contains(field))
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+impl LocalStore {
+    /// Runs `work` on the blocking thread pool, since it reads and writes files.
+    fn blocking<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&LocalStore) -> Result<T> + Send + 'static,
+    ) -> BoxFuture<'static, Result<T>> {
+        let store = self.clone();
+        Box::pin(async move { actix_rt::task::spawn_blocking(move || work(&store)).await? })
+    }
+}
+
+impl VectorStore for LocalStore {
+    fn create_collection(
+        &self,
+        collection_name: String,
+        dimension: u64,
+        distance: Distance,
+    ) -> BoxFuture<'static, Result<()>> {
+        self.blocking(move |store| store.create_collection_now(&collection_name, dimension, distance))
+    }
+
+    fn list_collections(&self) -> BoxFuture<'static, Result<Vec<String>>> {
+        self.blocking(|store| store.list_collections_now())
+    }
+
+    fn collection_info(&self, collection_name: String) -> BoxFuture<'static, Result<Option<CollectionDetails>>> {
+        self.blocking(move |store| store.collection_info_now(&collection_name))
+    }
+
+    fn delete_collection(&self, collection_name: String) -> BoxFuture<'static, Result<bool>> {
+        self.blocking(move |store| store.delete_collection_now(&collection_name))
+    }
+
+    fn upsert(&self, collection_name: String, points: Vec<Point>) -> BoxFuture<'static, Result<()>> {
+        self.blocking(move |store| store.upsert_now(&collection_name, points))
+    }
+
+    fn delete(&self, collection_name: String, filter: Filter) -> BoxFuture<'static, Result<()>> {
+        self.blocking(move |store| store.delete_now(&collection_name, &filter))
+    }
+
+    fn search(&self, request: SearchRequest) -> BoxFuture<'static, Result<Vec<SearchHit>>> {
+        self.blocking(move |store| store.search_now(request))
+    }
+
+    fn scroll(&self, collection_name: String, fields: Vec<String>) -> BoxFuture<'static, Result<Vec<Payload>>> {
+        self.blocking(move |store| store.scroll_now(&collection_name, &fields))
+    }
+
+    fn flush(&self) -> BoxFuture<'static, Result<()>> {
+        self.blocking(|store| store.collections.lock().unwrap().flush())
+    }
+}
+
+fn normalise(mut vector: Vec<f32>) -> Vec<f32> {
+    let length = dot(&vector, &vector).sqrt();
+    if length > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= length);
+    }
+    vector
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
@@ -1,32 +1,40 @@
-use actix::prelude::*;
+use anyhow::{anyhow, bail, Result};
 
-use anyhow::Result;
-
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
+use futures::future::BoxFuture;
 use qdrant_client::{
     prelude::*,
-    qdrant::{with_payload_selector::SelectorOptions, PayloadIncludeSelector, WithPayloadSelector},
+    qdrant::{
+        points_selector::PointsSelectorOneOf, value::Kind, vectors_config::Config,
+        with_payload_selector::SelectorOptions, Condition as QdrantCondition, CreateCollection,
+        Distance as QdrantDistance,
+        Filter as QdrantFilter, ListValue, PayloadIncludeSelector, PointsSelector, Range as QdrantRange,
+        ScrollPoints, Struct, Value, VectorParams, VectorsConfig, WithPayloadSelector,
+    },
 };
 
-#[derive(Message)]
-#[rtype(result = "Result<String>")]
-pub struct SearchRequest {
-    pub collection_name: String,
-    pub vector: Vec<f32>,
-}
+use crate::vector_store::{
+    self, CollectionDetails, Condition, Distance, Filter, Point, SearchHit, SearchRequest, VectorStore,
+    TEXT_FIELD,
+};
 
+/// How many points are fetched per page when scrolling
+const SCROLL_PAGE: u32 = 256;
+
+/// Vectors kept in a Qdrant server.
 pub struct QdrantStore {
     client: Arc<QdrantClient>,
 }
 
-impl Actor for QdrantStore {
-    type Context = Context<Self>;
-}
-
 impl QdrantStore {
+    /// Connects to Qdrant's gRPC port on localhost.
     pub async fn new() -> Self {
-        let config = QdrantClientConfig::from_url("http://localhost:6334");
+        Self::with_url("http://localhost:6334").await
+    }
+
+    pub async fn with_url(url: &str) -> Self {
+        let config = QdrantClientConfig::from_url(url);
         let client = QdrantClient::new(Some(config))
             .await
             .expect("Failed to create client");
@@ -37,47 +45,329 @@ impl QdrantStore {
     }
 }
 
-impl Handler<SearchRequest> for QdrantStore {
-    type Result = ResponseFuture<Result<String>>;
+async fn has_collection(client: &QdrantClient, collection_name: &str) -> Result<bool> {
+    let collections = client.list_collections().await?;
+    Ok(collections
+        .collections
+        .iter()
+        .any(|collection| collection.name == collection_name))
+}
+
+fn qdrant_distance(distance: Distance) -> QdrantDistance {
+    match distance {
+        Distance::Cosine => QdrantDistance::Cosine,
+        Distance::Dot => QdrantDistance::Dot,
+        Distance::Euclid => QdrantDistance::Euclid,
+    }
+}
+
+fn qdrant_filter(filter: Filter) -> Result<QdrantFilter> {
+    let conditions = |conditions: Vec<Condition>| -> Result<Vec<QdrantCondition>> {
+        conditions.into_iter().map(qdrant_condition).collect()
+    };
+    Ok(QdrantFilter {
+        must: conditions(filter.must)?,
+        should: conditions(filter.should)?,
+        must_not: conditions(filter.must_not)?,
+    })
+}
+
+fn qdrant_condition(condition: Condition) -> Result<QdrantCondition> {
+    Ok(match condition {
+        Condition::Match { field, value } => match value {
+            serde_json::Value::String(value) => QdrantCondition::matches(field, value),
+            serde_json::Value::Bool(value) => QdrantCondition::matches(field, value),
+            // A range compares as floats, so 3 matches 3.0 as it does in the local store
+            serde_json::Value::Number(number) => {
+                let number = number.as_f64();
+                QdrantCondition::range(
+                    field,
+                    QdrantRange {
+                        gte: number,
+                        lte: number,
+                        ..Default::default()
+                    },
+                )
+            }
+            value => bail!("Can't match {} against {}", field, value),
+        },
+        Condition::Range { field, range } => QdrantCondition::range(
+            field,
+            QdrantRange {
+                gt: range.gt,
+                gte: range.gte,
+                lt: range.lt,
+                lte: range.lte,
+            },
+        ),
+    })
+}
+
+impl VectorStore for QdrantStore {
+    fn create_collection(
+        &self,
+        collection_name: String,
+        dimension: u64,
+        distance: Distance,
+    ) -> BoxFuture<'static, Result<()>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if has_collection(&client, &collection_name).await? {
+                return Ok(());
+            }
+
+            println!(
+                "Qdrant       : Creating collection {} with dimension {} and {} distance",
+                collection_name, dimension, distance
+            );
+            client
+                .create_collection(&CreateCollection {
+                    collection_name,
+                    vectors_config: Some(VectorsConfig {
+                        config: Some(Config::Params(VectorParams {
+                            size: dimension,
+                            distance: qdrant_distance(distance).into(),
+                            ..Default::default()
+                        })),
+                    }),
+                    ..Default::default()
+                })
+                .await?;
+
+            Ok(())
+        })
+    }
+
+    fn list_collections(&self) -> BoxFuture<'static, Result<Vec<String>>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let collections = client.list_collections().await?;
+            Ok(collections
+                .collections
+                .into_iter()
+                .map(|collection| collection.name)
+                .collect())
+        })
+    }
+
+    fn collection_info(&self, collection_name: String) -> BoxFuture<'static, Result<Option<CollectionDetails>>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if !has_collection(&client, &collection_name).await? {
+                return Ok(None);
+            }
+
+            let info = client
+                .collection_info(&collection_name)
+                .await?
+                .result
+                .ok_or_else(|| anyhow!("Qdrant sent no info for {}", collection_name))?;
+            let params = info
+                .config
+                .and_then(|config| config.params)
+                .and_then(|params| params.vectors_config)
+                .and_then(|vectors| vectors.config);
+            let (dimension, distance) = match params {
+                Some(Config::Params(params)) => (params.size, params.distance),
+                _ => bail!("{} doesn't have a single unnamed vector", collection_name),
+            };
+            let distance = match QdrantDistance::from_i32(distance) {
+                Some(QdrantDistance::Dot) => Distance::Dot,
+                Some(QdrantDistance::Euclid) => Distance::Euclid,
+                _ => Distance::Cosine,
+            };
+
+            Ok(Some(CollectionDetails {
+                name: collection_name,
+                points: info.points_count,
+                dimension,
+                distance,
+            }))
+        })
+    }
+
+    fn delete_collection(&self, collection_name: String) -> BoxFuture<'static, Result<bool>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if !has_collection(&client, &collection_name).await? {
+                return Ok(false);
+            }
+
+            println!("Qdrant       : Deleting collection {}", collection_name);
+            client.delete_collection(&collection_name).await?;
+            Ok(true)
+        })
+    }
+
+    fn upsert(&self, collection_name: String, points: Vec<Point>) -> BoxFuture<'static, Result<()>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let points = points
+                .into_iter()
+                .map(|point| {
+                    let payload = point
+                        .payload
+                        .into_iter()
+                        .map(|(field, value)| (field, qdrant_value(value)))
+                        .collect();
+                    PointStruct::new(point.id, point.vector, Payload::new_from_hashmap(payload))
+                })
+                .collect();
+            client
+                .upsert_points_blocking(collection_name, points, None)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete(&self, collection_name: String, filter: Filter) -> BoxFuture<'static, Result<()>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            client
+                .delete_points_blocking(
+                    collection_name,
+                    &PointsSelector {
+                        points_selector_one_of: Some(PointsSelectorOneOf::Filter(qdrant_filter(filter)?)),
+                    },
+                    None,
+                )
+                .await?;
+            Ok(())
+        })
+    }
 
-    fn handle(&mut self, msg: SearchRequest, _ctx: &mut Self::Context) -> Self::Result {
+    fn search(&self, request: SearchRequest) -> BoxFuture<'static, Result<Vec<SearchHit>>> {
         let client = self.client.clone();
 
         Box::pin(async move {
+            let with_payload = match request.fields {
+                Some(mut fields) => {
+                    fields.push(TEXT_FIELD.to_string());
+                    SelectorOptions::Include(PayloadIncludeSelector { fields })
+                }
+                None => SelectorOptions::Enable(true),
+            };
             let search_result = client
                 .search_points(&SearchPoints {
-                    collection_name: msg.collection_name,
-                    vector: msg.vector,
-                    filter: None,
-                    limit: 1,
+                    collection_name: request.collection_name,
+                    vector: request.vector,
+                    filter: request.filter.map(qdrant_filter).transpose()?,
+                    limit: request.limit,
                     with_vectors: None,
                     with_payload: Some(WithPayloadSelector {
-                        selector_options: Some(SelectorOptions::Include(PayloadIncludeSelector {
-                            fields: vec!["city".to_string()],
-                        })),
+                        selector_options: Some(with_payload),
                     }),
                     params: None,
-                    score_threshold: None,
+                    score_threshold: request.score_threshold,
                     offset: None,
                     ..Default::default()
                 })
-                .await
-                .expect("Error searching");
-
-            let point = search_result.result.first().unwrap();
-
-            let m = point.payload.get(&"city".to_string()).unwrap();
-            let v = match &m.kind {
-                Some(v) => match v {
-                    qdrant_client::qdrant::value::Kind::StringValue(value) => value.clone(),
-                    _ => todo!(),
-                },
-                None => todo!(),
-            };
+                .await?;
 
-            // vec!["ddd".into()];
+            let hits = search_result
+                .result
+                .into_iter()
+                .filter_map(|point| SearchHit::from_payload(json_payload(point.payload), point.score))
+                .collect();
 
-            Ok(v)
+            Ok(hits)
         })
     }
+
+    fn scroll(
+        &self,
+        collection_name: String,
+        fields: Vec<String>,
+    ) -> BoxFuture<'static, Result<Vec<vector_store::Payload>>> {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if !has_collection(&client, &collection_name).await? {
+                return Ok(vec![]);
+            }
+
+            let mut payloads = vec![];
+            let mut offset = None;
+            loop {
+                let page = client
+                    .scroll(&ScrollPoints {
+                        collection_name: collection_name.clone(),
+                        filter: None,
+                        offset,
+                        limit: Some(SCROLL_PAGE),
+                        with_payload: Some(WithPayloadSelector {
+                            selector_options: Some(SelectorOptions::Include(PayloadIncludeSelector {
+                                fields: fields.clone(),
+                            })),
+                        }),
+                        with_vectors: None,
+                        ..Default::default()
+                    })
+                    .await?;
+
+                payloads.extend(page.result.into_iter().map(|point| json_payload(point.payload)));
+
+                match page.next_page_offset {
+                    Some(next) => offset = Some(next),
+                    None => break,
+                }
+            }
+
+            Ok(payloads)
+        })
+    }
+}
+
+fn json_payload(payload: HashMap<String, Value>) -> vector_store::Payload {
+    payload
+        .into_iter()
+        .map(|(field, value)| (field, json_value(value)))
+        .collect()
+}
+
+/// Converts a payload value from Qdrant's representation.
+pub fn json_value(value: Value) -> serde_json::Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => serde_json::Value::Null,
+        Some(Kind::BoolValue(value)) => value.into(),
+        Some(Kind::IntegerValue(value)) => value.into(),
+        Some(Kind::DoubleValue(value)) => value.into(),
+        Some(Kind::StringValue(value)) => value.into(),
+        Some(Kind::ListValue(list)) => list.values.into_iter().map(json_value).collect(),
+        Some(Kind::StructValue(object)) => object
+            .fields
+            .into_iter()
+            .map(|(field, value)| (field, json_value(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+/// Converts a payload value to Qdrant's representation.
+pub fn qdrant_value(value: serde_json::Value) -> Value {
+    let kind = match value {
+        serde_json::Value::Null => Kind::NullValue(0),
+        serde_json::Value::Bool(value) => Kind::BoolValue(value),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) => Kind::IntegerValue(value),
+            None => Kind::DoubleValue(number.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(value) => Kind::StringValue(value),
+        serde_json::Value::Array(values) => Kind::ListValue(ListValue {
+            values: values.into_iter().map(qdrant_value).collect(),
+        }),
+        serde_json::Value::Object(object) => Kind::StructValue(Struct {
+            fields: object
+                .into_iter()
+                .map(|(field, value)| (field, qdrant_value(value)))
+                .collect(),
+        }),
+    };
+    Value { kind: Some(kind) }
 }
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use actix::prelude::*;
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use crate::{document_loader::DirectoryLoader, indexer::Index};
+
+/// How long a directory has to be quiet before it's re-indexed, unless set with `debounce`
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Keep a collection in sync with the files under `root` that match `pattern`.
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct Watch {
+    pub root: PathBuf,
+    pub pattern: String,
+    pub collection_name: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Unwatch(pub PathBuf);
+
+/// Something changed under a watched root. Sent by notify, but anyone can send it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Changed(pub PathBuf);
+
+struct Watched {
+    pattern: String,
+    collection_name: String,
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    /// The re-index waiting for things to quieten down
+    pending: Option<SpawnHandle>,
+    indexing: bool,
+    /// Changed again while indexing, so go again once done
+    dirty: bool,
+}
+
+/// Re-indexes directories as their files change, so search always reflects what's on disk.
+pub struct Watcher {
+    indexer: Recipient<Index>,
+    debounce: Duration,
+    watched: HashMap<PathBuf, Watched>,
+}
+
+impl Actor for Watcher {
+    type Context = Context<Self>;
+}
+
+impl Watcher {
+    pub fn with(indexer: Recipient<Index>) -> Self {
+        Self {
+            indexer,
+            debounce: DEFAULT_DEBOUNCE,
+            watched: HashMap::new(),
+        }
+    }
+
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    fn reindex(&mut self, root: PathBuf, ctx: &mut Context<Self>) {
+        let Some(watched) = self.watched.get_mut(&root) else {
+            return;
+        };
+        watched.pending = None;
+        if watched.indexing {
+            watched.dirty = true;
+            return;
+        }
+        watched.indexing = true;
+        watched.dirty = false;
+
+        println!("Watcher      : Re-indexing {}", root.display());
+        let index = Index {
+            paths: DirectoryLoader::find_files_with_pattern(&root, &watched.pattern)
+                .paths()
+                .to_vec(),
+            collection_name: watched.collection_name.clone(),
+        };
+
+        self.indexer
+            .send(index)
+            .into_actor(self)
+            .map(move |result, act, ctx| {
+                match result {
+                    Ok(Ok(report)) => println!("Watcher      : {}", report),
+                    Ok(Err(e)) => eprintln!("Watcher      : Unable to re-index {}: {}", root.display(), e),
+                    Err(e) => eprintln!("Watcher      : Indexer is gone: {}", e),
+                }
+
+                if let Some(watched) = act.watched.get_mut(&root) {
+                    watched.indexing = false;
+                    if watched.dirty {
+                        act.reindex(root, ctx);
+                    }
+                }
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Handler<Watch> for Watcher {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: Watch, ctx: &mut Context<Self>) -> Self::Result {
+        if self.watched.contains_key(&msg.root) {
+            return Ok(());
+        }
+
+        // notify calls back on its own thread
+        let addr = ctx.address();
+        let root = msg.root.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            match event {
+                Ok(event) if event.kind.is_access() => {}
+                Ok(_) => addr.do_send(Changed(root.clone())),
+                Err(e) => eprintln!("Watcher      : {}", e),
+            }
+        })?;
+        watcher.watch(Path::new(&msg.root), RecursiveMode::Recursive)?;
+
+        println!("Watcher      : Watching {}", msg.root.display());
+        self.watched.insert(
+            msg.root,
+            Watched {
+                pattern: msg.pattern,
+                collection_name: msg.collection_name,
+                _watcher: watcher,
+                pending: None,
+                indexing: false,
+                dirty: false,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl Handler<Unwatch> for Watcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unwatch, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(watched) = self.watched.remove(&msg.0) {
+            println!("Watcher      : Stopped watching {}", msg.0.display());
+            if let Some(pending) = watched.pending {
+                ctx.cancel_future(pending);
+            }
+        }
+    }
+}
+
+impl Handler<Changed> for Watcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: Changed, ctx: &mut Context<Self>) -> Self::Result {
+        let Some(watched) = self.watched.get_mut(&msg.0) else {
+            return;
+        };
+
+        // Start the wait over on every change, so a burst of saves only re-indexes once
+        if let Some(pending) = watched.pending.take() {
+            ctx.cancel_future(pending);
+        }
+        let root = msg.0;
+        watched.pending = Some(ctx.run_later(self.debounce, move |act, ctx| act.reindex(root, ctx)));
+    }
+}
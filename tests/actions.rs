@@ -0,0 +1,83 @@
+use actor_demo::{interpreter::Action, llm::action_functions, vector_store::Distance};
+use serde_json::json;
+
+#[test]
+fn collection_actions_parse_from_function_calls() {
+    let action =
+        Action::from_function_call("createcollection", r#"{"name": "project-x", "distance": "dot"}"#).unwrap();
+    assert!(matches!(
+        action,
+        Action::CreateCollection { name, dimension: None, distance: Some(Distance::Dot) } if name == "project-x"
+    ));
+
+    let action = Action::from_function_call("listcollections", "").unwrap();
+    assert!(matches!(action, Action::ListCollections));
+
+    let action = Action::from_function_call("deletecollection", r#"{"name": "archive"}"#).unwrap();
+    assert!(matches!(action, Action::DeleteCollection { name } if name == "archive"));
+}
+
+#[test]
+fn every_action_is_offered_as_a_function() {
+    let functions = action_functions();
+    let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "search",
+            "writetofile",
+            "retrievedocuments",
+            "indexdocuments",
+            "getstdinput",
+            "createcollection",
+            "listcollections",
+            "collectioninfo",
+            "deletecollection",
+        ]
+    );
+
+    let parameters = |name: &str| {
+        functions
+            .iter()
+            .find(|f| f.name == name)
+            .and_then(|f| f.parameters.clone())
+            .unwrap()
+    };
+    assert_eq!(parameters("search")["required"], json!(["collection", "query"]));
+    assert_eq!(parameters("writetofile")["required"], json!(["content", "filename"]));
+    assert_eq!(parameters("createcollection")["required"], json!(["name"]));
+
+    // The function name stands in for the `type` tag
+    for function in &functions {
+        let parameters = function.parameters.clone().unwrap();
+        assert!(parameters["properties"].get("type").is_none(), "{} has a type", function.name);
+        assert!(
+            !parameters["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|field| field == "type"),
+            "{} requires a type",
+            function.name
+        );
+    }
+}
+
+#[test]
+fn actions_round_trip_through_function_calls() {
+    let arguments = json!({"query": "birds", "collection": "docs"});
+    let action = Action::from_function_call("search", &arguments.to_string()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&action).unwrap(),
+        json!({"type": "search", "query": "birds", "collection": "docs"})
+    );
+
+    let action = Action::from_function_call("indexdocuments", "{}").unwrap();
+    assert_eq!(
+        serde_json::to_value(&action).unwrap(),
+        json!({"type": "indexdocuments", "collection": null})
+    );
+
+    assert!(Action::from_function_call("search", r#"{"query": "birds"}"#).is_err());
+    assert!(Action::from_function_call("summon", "{}").is_err());
+}
@@ -0,0 +1,75 @@
+use actor_demo::{
+    chunker::{Chunker, Strategy},
+    document_loader::Document,
+};
+
+fn document(id: &str, text: &str) -> Document {
+    Document::new(id.into(), text.into())
+}
+
+#[test]
+fn recursive_splits_on_headings_first() {
+    let text = "# Setup\n\nInstall the tools. Then check they work.\n\n# Usage\n\nRun it with a folder of notes. Ask it anything.\n";
+    let chunker = Chunker::new(Strategy::Recursive { size: 20 }).unwrap();
+
+    let chunks = chunker.chunk(&document("notes.md", text));
+
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks[0].text.starts_with("# Setup"));
+    assert!(chunks[1].text.starts_with("# Usage"));
+    for chunk in &chunks {
+        assert_eq!(chunk.parent, "notes.md");
+        assert_eq!(&text[chunk.start..chunk.end], chunk.text);
+    }
+}
+
+#[test]
+fn paragraphs_split_at_blank_lines() {
+    let text = "First paragraph here.\n\nSecond paragraph here.\n";
+    let chunker = Chunker::new(Strategy::Recursive { size: 6 }).unwrap();
+
+    let chunks = chunker.chunk(&document("notes.txt", text));
+
+    let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+    assert_eq!(texts, vec!["First paragraph here.", "Second paragraph here."]);
+    assert_eq!((chunks[1].start, chunks[1].end), (23, 45));
+}
+
+#[test]
+fn token_windows_overlap() {
+    let text: Vec<String> = (0..50).map(|i| format!("word{}", i)).collect();
+    let text = text.join(" ");
+    let chunker = Chunker::new(Strategy::Tokens { size: 20, overlap: 6 }).unwrap();
+
+    let chunks = chunker.chunk(&document("words.txt", &text));
+
+    assert!(chunks.len() > 2);
+    assert_eq!(chunks[0].start, 0);
+    assert_eq!(chunks.last().unwrap().end, text.len());
+    for pair in chunks.windows(2) {
+        assert!(pair[1].start < pair[0].end);
+        assert!(pair[1].start > pair[0].start);
+    }
+}
+
+#[test]
+fn source_files_split_between_items() {
+    let text = "fn one() {\n    let a = 1;\n\n    a + 1\n}\n\nfn two() {\n    let b = 2;\n\n    b + 2\n}\n";
+    let chunker = Chunker::default()
+        .code(Strategy::Code { size: 24 })
+        .unwrap();
+
+    let chunks = chunker.chunk(&document("src/lib.rs", text));
+
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks[0].text.starts_with("fn one"));
+    assert!(chunks[0].text.ends_with('}'));
+    assert!(chunks[1].text.starts_with("fn two"));
+}
+
+#[test]
+fn strategies_are_checked() {
+    assert!(Chunker::new(Strategy::Tokens { size: 10, overlap: 10 }).is_err());
+    assert!(Chunker::new(Strategy::Recursive { size: 0 }).is_err());
+    assert!(Chunker::default().chunk(&document("empty.txt", "")).is_empty());
+}
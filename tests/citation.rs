@@ -0,0 +1,85 @@
+use actor_demo::citation::{strip_markers, Citations, Source};
+
+fn source(path: &str, start: usize, end: usize, score: f32) -> Source {
+    Source {
+        path: path.into(),
+        document: path.into(),
+        location: None,
+        start,
+        end,
+        score,
+    }
+}
+
+#[test]
+fn markers_are_stripped_from_speech() {
+    let (text, ids) = strip_markers("The sky is blue [c0a1b2c3d]. It rains [c00000001, cffffffff] often.");
+
+    assert_eq!(text, "The sky is blue. It rains often.");
+    assert_eq!(ids, vec!["c0a1b2c3d", "c00000001", "cffffffff"]);
+
+    let (text, ids) = strip_markers("[c0a1b2c3d]");
+    assert_eq!(text, "");
+    assert_eq!(ids, vec!["c0a1b2c3d"]);
+
+    let (text, _) = strip_markers("[c0a1b2c3d] It rains.");
+    assert_eq!(text, "It rains.");
+}
+
+#[test]
+fn other_brackets_are_left_alone() {
+    let (text, ids) = strip_markers("See [note] and [c] and [c1] and [cafe] and [c0a1b2c3z].");
+
+    assert_eq!(text, "See [note] and [c] and [c1] and [cafe] and [c0a1b2c3z].");
+    assert!(ids.is_empty());
+}
+
+#[test]
+fn the_same_chunk_keeps_its_id() {
+    let citations = Citations::default();
+
+    let first = citations.cite(source("notes.md", 0, 120, 0.8));
+    let second = citations.cite(source("notes.md", 120, 240, 0.7));
+    let again = citations.cite(source("notes.md", 0, 120, 0.9));
+
+    assert_ne!(first, second);
+    assert_eq!(again, first);
+    assert_eq!(first.len(), 9);
+    assert!(strip_markers(&format!("[{}]", first)).0.is_empty());
+    assert_eq!(citations.resolve(&first), Some(source("notes.md", 0, 120, 0.9)));
+    assert_eq!(citations.resolve("c00000000"), None);
+}
+
+#[test]
+fn ids_survive_a_restart() {
+    let before = Citations::default();
+    let id = before.cite(source("notes.md", 0, 120, 0.8));
+
+    // Ids don't depend on what else was cited, or in what order
+    let after = Citations::default();
+    after.cite(source("other.md", 0, 10, 0.5));
+    assert_eq!(after.cite(source("notes.md", 0, 120, 0.6)), id);
+
+    let restored = Citations::restore(vec![source("notes.md", 0, 120, 0.8)]);
+    assert_eq!(restored.resolve(&id), Some(source("notes.md", 0, 120, 0.8)));
+}
+
+#[test]
+fn pages_of_one_file_are_cited_apart() {
+    let page = |page: u32| Source {
+        path: "paper.pdf".into(),
+        document: format!("paper.pdf#{}", page - 1),
+        location: Some(format!("page {}", page)),
+        start: 0,
+        end: 120,
+        score: 0.8,
+    };
+
+    // The same offsets on different pages are different passages
+    let citations = Citations::default();
+    let first = citations.cite(page(1));
+    let second = citations.cite(page(2));
+    assert_ne!(first, second);
+    assert_eq!(citations.resolve(&second), Some(page(2)));
+    assert_eq!(page(2).to_string(), "paper.pdf, page 2, bytes 0..120 (score 0.80)");
+}
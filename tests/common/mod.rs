@@ -0,0 +1,50 @@
+use actix::prelude::*;
+use actor_demo::embedding::{EmbedBatch, EmbeddingQuery, EmbeddingStats, GetDimension, GetStats};
+use anyhow::{anyhow, bail, Result};
+
+/// Embeds "cats" and "dogs" as 2D vectors and refuses any other query. Batches
+/// get the same vector for every text, except that it refuses any with "FAIL" in them.
+pub struct StubEmbedding;
+
+impl Actor for StubEmbedding {
+    type Context = Context<Self>;
+}
+
+impl Handler<EmbeddingQuery> for StubEmbedding {
+    type Result = Result<Vec<f32>>;
+
+    fn handle(&mut self, msg: EmbeddingQuery, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg.0.as_str() {
+            "cats" => Ok(vec![1.0, 0.0]),
+            "dogs" => Ok(vec![0.0, 1.0]),
+            _ => Err(anyhow!("model fell over")),
+        }
+    }
+}
+
+impl Handler<EmbedBatch> for StubEmbedding {
+    type Result = Result<Vec<Vec<f32>>>;
+
+    fn handle(&mut self, msg: EmbedBatch, _ctx: &mut Context<Self>) -> Self::Result {
+        if msg.0.iter().any(|text| text.contains("FAIL")) {
+            bail!("model fell over");
+        }
+        Ok(msg.0.iter().map(|_| vec![1.0, 0.0]).collect())
+    }
+}
+
+impl Handler<GetDimension> for StubEmbedding {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: GetDimension, _ctx: &mut Context<Self>) -> Self::Result {
+        2
+    }
+}
+
+impl Handler<GetStats> for StubEmbedding {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _msg: GetStats, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(EmbeddingStats::default())
+    }
+}
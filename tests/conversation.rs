@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use actix::prelude::*;
+use actor_demo::{
+    conversation::{Conversation, GetTurn, Lifecycle, Start, Turn},
+    interpreter::{Action, Actions, GetObservations, Interpreter},
+    llm::{ChatMessage, INITIAL_PROMPT},
+    stt::SttAction,
+};
+use anyhow::Result;
+
+/// Stands in for the LLM, STT and the interpreter, and remembers what it was asked to do.
+#[derive(Default)]
+struct Stub {
+    sent: Vec<String>,
+    observations: VecDeque<String>,
+}
+
+impl Actor for Stub {
+    type Context = Context<Self>;
+}
+
+impl Handler<ChatMessage> for Stub {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: ChatMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        self.sent.push(format!("llm: {}", msg.0));
+        Ok(())
+    }
+}
+
+impl Handler<SttAction> for Stub {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: SttAction, _ctx: &mut Context<Self>) -> Self::Result {
+        let action = match msg {
+            SttAction::RecordUntilSilence => "record",
+            SttAction::ListenForBargeIn => "barge-in",
+            SttAction::Pause => "pause",
+        };
+        self.sent.push(format!("stt: {}", action));
+        Ok(())
+    }
+}
+
+impl Handler<GetObservations> for Stub {
+    type Result = Option<String>;
+
+    fn handle(&mut self, _msg: GetObservations, _ctx: &mut Context<Self>) -> Self::Result {
+        self.observations.pop_front()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+struct Sent;
+
+impl Handler<Sent> for Stub {
+    type Result = MessageResult<Sent>;
+
+    fn handle(&mut self, _msg: Sent, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(std::mem::take(&mut self.sent))
+    }
+}
+
+fn start(stub: Stub) -> (Addr<Stub>, Addr<Conversation>) {
+    let stub = stub.start();
+    let conversation = Conversation::with(
+        stub.clone().recipient(),
+        stub.clone().recipient(),
+        stub.clone().recipient(),
+    )
+    .start();
+    (stub, conversation)
+}
+
+/// Sends the events in order and returns the turn after the last one.
+async fn events(conversation: &Addr<Conversation>, events: &[Lifecycle]) -> Turn {
+    for event in events {
+        conversation.send(*event).await.unwrap();
+    }
+    // Anything the conversation is waiting on holds up its mailbox, so this comes last
+    conversation.send(GetTurn).await.unwrap()
+}
+
+async fn sent(stub: &Addr<Stub>) -> Vec<String> {
+    stub.send(Sent).await.unwrap()
+}
+
+#[actix::test]
+async fn starts_with_the_prompt() {
+    let (stub, conversation) = start(Stub::default());
+    conversation.send(Start { prompt: true }).await.unwrap();
+    assert_eq!(conversation.send(GetTurn).await.unwrap(), Turn::Think);
+    assert_eq!(sent(&stub).await, vec![format!("llm: {}", INITIAL_PROMPT)]);
+
+    // A resumed session goes straight to the user
+    let (stub, conversation) = start(Stub::default());
+    conversation.send(Start { prompt: false }).await.unwrap();
+    assert_eq!(conversation.send(GetTurn).await.unwrap(), Turn::Listen);
+    assert_eq!(sent(&stub).await, vec!["stt: record"]);
+}
+
+#[actix::test]
+async fn listen_think_speak_listen() {
+    let (stub, conversation) = start(Stub::default());
+    conversation.send(Start { prompt: false }).await.unwrap();
+    sent(&stub).await;
+
+    let turn = events(&conversation, &[Lifecycle::LlmStarted, Lifecycle::UtteranceQueued]).await;
+    assert_eq!(turn, Turn::Think);
+    assert!(sent(&stub).await.is_empty());
+
+    // Barge-in only starts listening once there's something to talk over
+    let turn = events(&conversation, &[Lifecycle::PlaybackStarted, Lifecycle::LlmFinished]).await;
+    assert_eq!(turn, Turn::Speak);
+    assert_eq!(sent(&stub).await, vec!["stt: barge-in"]);
+
+    let turn = events(&conversation, &[Lifecycle::UtterancePlayed]).await;
+    assert_eq!(turn, Turn::Listen);
+    assert_eq!(sent(&stub).await, vec!["stt: record"]);
+}
+
+#[actix::test]
+async fn actions_and_observations() {
+    let (stub, conversation) = start(Stub {
+        observations: VecDeque::from(["3 results".to_string()]),
+        ..Default::default()
+    });
+    conversation.send(Start { prompt: false }).await.unwrap();
+    sent(&stub).await;
+
+    let turn = events(
+        &conversation,
+        &[Lifecycle::LlmStarted, Lifecycle::ActionsStarted, Lifecycle::LlmFinished],
+    )
+    .await;
+    assert_eq!(turn, Turn::Act);
+
+    // The LLM sees the observation before the user gets a turn
+    let turn = events(&conversation, &[Lifecycle::ObservationReady, Lifecycle::ActionsFinished]).await;
+    assert_eq!(turn, Turn::Think);
+    assert_eq!(sent(&stub).await, vec!["llm: 3 results"]);
+
+    let turn = events(&conversation, &[Lifecycle::LlmStarted, Lifecycle::LlmFinished]).await;
+    assert_eq!(turn, Turn::Listen);
+    assert_eq!(sent(&stub).await, vec!["stt: record"]);
+}
+
+#[actix::test]
+async fn missing_observation_goes_back_to_the_user() {
+    let (stub, conversation) = start(Stub::default());
+    conversation.send(Start { prompt: false }).await.unwrap();
+    sent(&stub).await;
+
+    let turn = events(
+        &conversation,
+        &[
+            Lifecycle::LlmStarted,
+            Lifecycle::ActionsStarted,
+            Lifecycle::LlmFinished,
+            Lifecycle::ObservationReady,
+            Lifecycle::ActionsFinished,
+        ],
+    )
+    .await;
+    assert_eq!(turn, Turn::Listen);
+    assert_eq!(sent(&stub).await, vec!["stt: record"]);
+}
+
+#[actix::test]
+async fn interrupted_while_speaking() {
+    let (stub, conversation) = start(Stub::default());
+    conversation.send(Start { prompt: false }).await.unwrap();
+    sent(&stub).await;
+
+    let turn = events(
+        &conversation,
+        &[
+            Lifecycle::LlmStarted,
+            Lifecycle::UtteranceQueued,
+            Lifecycle::UtteranceQueued,
+            Lifecycle::LlmFinished,
+            Lifecycle::PlaybackStarted,
+        ],
+    )
+    .await;
+    assert_eq!(turn, Turn::Speak);
+    assert_eq!(sent(&stub).await, vec!["stt: barge-in"]);
+
+    // What the user said is on its way to the LLM, so there's no recording in between,
+    // even once the cancelled utterances have been dropped
+    let turn = events(
+        &conversation,
+        &[
+            Lifecycle::UserInterrupted,
+            Lifecycle::UtterancePlayed,
+            Lifecycle::UtterancePlayed,
+        ],
+    )
+    .await;
+    assert_eq!(turn, Turn::Think);
+    assert!(sent(&stub).await.is_empty());
+
+    let turn = events(&conversation, &[Lifecycle::LlmStarted, Lifecycle::LlmFinished]).await;
+    assert_eq!(turn, Turn::Listen);
+    assert_eq!(sent(&stub).await, vec!["stt: record"]);
+}
+
+#[actix::test]
+async fn every_observation_reaches_the_llm_in_order() {
+    let stub = Stub::default().start();
+    let conversation_ctx = Context::<Conversation>::new();
+    let interpreter = Interpreter::with()
+        .events(conversation_ctx.address().recipient())
+        .start();
+    let conversation = conversation_ctx.run(Conversation::with(
+        stub.clone().recipient(),
+        stub.clone().recipient(),
+        interpreter.clone().recipient(),
+    ));
+    conversation.send(Start { prompt: false }).await.unwrap();
+    sent(&stub).await;
+
+    let turn = events(
+        &conversation,
+        &[Lifecycle::LlmStarted, Lifecycle::ActionsStarted, Lifecycle::LlmFinished],
+    )
+    .await;
+    assert_eq!(turn, Turn::Act);
+
+    // Neither has anything to work with, so each makes its own observation
+    let actions = vec![
+        Action::Search {
+            query: "cats".into(),
+            collection: "docs".into(),
+        },
+        Action::ListCollections,
+    ];
+    interpreter.send(Actions(actions)).await.unwrap().unwrap();
+    assert_eq!(conversation.send(GetTurn).await.unwrap(), Turn::Think);
+    assert_eq!(sent(&stub).await, vec!["llm: Search is not available."]);
+
+    // The second is fetched once the LLM is done with the first
+    let turn = events(&conversation, &[Lifecycle::LlmStarted, Lifecycle::LlmFinished]).await;
+    assert_eq!(turn, Turn::Think);
+    assert_eq!(sent(&stub).await, vec!["llm: Collections are not available."]);
+
+    let turn = events(&conversation, &[Lifecycle::LlmStarted, Lifecycle::LlmFinished]).await;
+    assert_eq!(turn, Turn::Listen);
+    assert_eq!(sent(&stub).await, vec!["stt: record"]);
+}
@@ -0,0 +1,156 @@
+use std::{fs, sync::Mutex, time::Duration};
+
+use actor_demo::embedding::{encode_in_batches, EmbeddingConfig, EmbeddingModel, EmbeddingStats, ModelSource};
+use anyhow::bail;
+use rust_bert::pipelines::sentence_embeddings::SentenceEmbeddingsModelType;
+use tempfile::TempDir;
+
+#[test]
+fn parses_model_names() {
+    assert_eq!(
+        "all-minilm-l12-v2".parse::<ModelSource>().unwrap(),
+        ModelSource::Remote(SentenceEmbeddingsModelType::AllMiniLmL12V2)
+    );
+    assert_eq!(ModelSource::default().to_string(), "all-MiniLM-L6-v2");
+    assert!("no-such-model".parse::<ModelSource>().is_err());
+
+    let dir = std::env::temp_dir();
+    assert_eq!(
+        dir.to_str().unwrap().parse::<ModelSource>().unwrap(),
+        ModelSource::Local(dir)
+    );
+}
+
+#[test]
+fn offline_mode_needs_the_model_on_disk() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let model_dir = dir.join("all-MiniLM-L6-v2");
+    fs::create_dir_all(&model_dir).unwrap();
+
+    // Online, a missing model is downloaded
+    let mut config = EmbeddingConfig {
+        models_dir: dir.to_path_buf(),
+        ..Default::default()
+    };
+    assert!(config.check().is_ok());
+
+    config.offline = true;
+    let error = config.check().unwrap_err().to_string();
+    assert!(error.contains("rust_model.ot"));
+    assert!(error.contains("all-MiniLM-L6-v2"));
+
+    let modules = r#"[
+        {"idx": 0, "name": "0", "path": "", "type": "sentence_transformers.models.Transformer"},
+        {"idx": 1, "name": "1", "path": "1_Pooling", "type": "sentence_transformers.models.Pooling"},
+        {"idx": 2, "name": "2", "path": "2_Normalize", "type": "sentence_transformers.models.Normalize"}
+    ]"#;
+    fs::write(model_dir.join("modules.json"), modules).unwrap();
+    fs::write(model_dir.join("config.json"), r#"{"model_type": "bert"}"#).unwrap();
+    fs::write(model_dir.join("sentence_bert_config.json"), "{}").unwrap();
+    fs::write(model_dir.join("rust_model.ot"), "").unwrap();
+
+    // What else is needed depends on the modules and the model type
+    let error = config.check().unwrap_err().to_string();
+    assert!(error.contains("1_Pooling/config.json, vocab.txt"));
+    assert!(!error.contains("rust_model.ot"));
+
+    fs::create_dir_all(model_dir.join("1_Pooling")).unwrap();
+    fs::write(model_dir.join("1_Pooling/config.json"), "{}").unwrap();
+    fs::write(model_dir.join("vocab.txt"), "").unwrap();
+    assert!(config.check().is_ok());
+
+    fs::write(model_dir.join("config.json"), r#"{"model_type": "roberta"}"#).unwrap();
+    assert!(config.check().unwrap_err().to_string().contains("vocab.json, merges.txt"));
+    fs::write(model_dir.join("config.json"), r#"{"model_type": "gpt2"}"#).unwrap();
+    assert!(config.check().unwrap_err().to_string().contains("\"gpt2\""));
+}
+
+#[actix::test]
+async fn a_pool_that_cant_load_its_model_is_an_error() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let model_dir = dir.join("all-MiniLM-L6-v2");
+    fs::create_dir_all(&model_dir).unwrap();
+    let config = EmbeddingConfig {
+        models_dir: dir.to_path_buf(),
+        offline: true,
+        ..Default::default()
+    };
+
+    // Missing files are found before any thread starts
+    let error = EmbeddingModel::pool(2, config.clone()).unwrap_err().to_string();
+    assert!(error.contains("rust_model.ot"));
+
+    // Every file is there, but the weights are empty, so each thread fails to load them
+    let modules = r#"[
+        {"idx": 0, "name": "0", "path": "", "type": "sentence_transformers.models.Transformer"},
+        {"idx": 1, "name": "1", "path": "1_Pooling", "type": "sentence_transformers.models.Pooling"}
+    ]"#;
+    fs::write(model_dir.join("modules.json"), modules).unwrap();
+    fs::write(model_dir.join("config.json"), r#"{"model_type": "bert"}"#).unwrap();
+    fs::write(model_dir.join("sentence_bert_config.json"), "{}").unwrap();
+    fs::write(model_dir.join("rust_model.ot"), "").unwrap();
+    fs::create_dir_all(model_dir.join("1_Pooling")).unwrap();
+    fs::write(model_dir.join("1_Pooling/config.json"), "{}").unwrap();
+    fs::write(model_dir.join("vocab.txt"), "").unwrap();
+    assert!(config.check().is_ok());
+    assert!(EmbeddingModel::pool(2, config).is_err());
+}
+
+#[test]
+fn stats_report_throughput() {
+    let stats = EmbeddingStats {
+        texts: 300,
+        batches: 10,
+        busy: Duration::from_millis(1500),
+    };
+    assert_eq!(stats.texts_per_second(), 200.0);
+    assert_eq!(stats.to_string(), "300 texts in 10 batches, 1.5s in the model (200 texts/s)");
+
+    // Nothing embedded yet isn't infinitely fast
+    assert_eq!(EmbeddingStats::default().texts_per_second(), 0.0);
+}
+
+#[test]
+fn encodes_in_batches_and_counts_them() {
+    let texts: Vec<String> = (0..7).map(|i| i.to_string()).collect();
+    let stats = Mutex::new(EmbeddingStats::default());
+    let mut sizes = vec![];
+    let embeddings = encode_in_batches(&texts, 3, &stats, |batch| {
+        sizes.push(batch.len());
+        Ok(batch.iter().map(|text| vec![text.parse().unwrap()]).collect())
+    })
+    .unwrap();
+
+    assert_eq!(sizes, vec![3, 3, 1]);
+    // Still in the order they were sent
+    let firsts: Vec<f32> = embeddings.iter().map(|vector| vector[0]).collect();
+    assert_eq!(firsts, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    let stats = *stats.lock().unwrap();
+    assert_eq!((stats.texts, stats.batches), (7, 3));
+
+    // A batch size of 0 is taken as 1
+    let stats = Mutex::new(EmbeddingStats::default());
+    encode_in_batches(&texts[..2], 0, &stats, |batch| Ok(vec![vec![0.0]; batch.len()])).unwrap();
+    assert_eq!(stats.lock().unwrap().batches, 2);
+}
+
+#[test]
+fn failed_batches_are_errors() {
+    let texts: Vec<String> = (0..4).map(|i| i.to_string()).collect();
+    let stats = Mutex::new(EmbeddingStats::default());
+
+    let short = encode_in_batches(&texts, 2, &stats, |_| Ok(vec![vec![0.0]]));
+    assert!(short.unwrap_err().to_string().contains("1 embeddings for 2 texts"));
+
+    let failed = encode_in_batches(&texts, 2, &stats, |batch| {
+        if batch[0] == "2" {
+            bail!("model fell over");
+        }
+        Ok(vec![vec![0.0]; batch.len()])
+    });
+    assert_eq!(failed.unwrap_err().to_string(), "model fell over");
+    // Only batches that made it are counted
+    assert_eq!(stats.lock().unwrap().batches, 1);
+}
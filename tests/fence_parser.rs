@@ -0,0 +1,120 @@
+use actor_demo::fence_parser::{Event, FenceParser, ParseError, Segment};
+
+fn parse(tokens: &[&str]) -> Vec<Event> {
+    let mut parser = FenceParser::default();
+    let mut events: Vec<Event> = tokens.iter().flat_map(|token| parser.push(token)).collect();
+    events.extend(parser.finish());
+    events
+}
+
+fn text(text: &str) -> Event {
+    Event::Segment(Segment::Text(text.into()))
+}
+
+fn block(lang: &str, content: &str) -> Event {
+    Event::Segment(Segment::Block {
+        lang: lang.into(),
+        content: content.into(),
+    })
+}
+
+#[test]
+fn fences_split_across_tokens() {
+    let events = parse(&["Hi ", "``", "`json", "\n{\"a\": 1}\n", "``", "`", " bye"]);
+    assert_eq!(
+        events,
+        vec![text("Hi "), block("json", "{\"a\": 1}\n"), text(" bye")]
+    );
+}
+
+#[test]
+fn one_character_at_a_time() {
+    let input = "Sure.\n```speech\nHello there.\n```\n";
+    let tokens: Vec<String> = input.chars().map(String::from).collect();
+    let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    assert_eq!(
+        parse(&tokens),
+        vec![text("Sure.\n"), block("speech", "Hello there.\n")]
+    );
+}
+
+#[test]
+fn prose_outside_blocks() {
+    assert_eq!(parse(&["Just ", "some ", "prose."]), vec![text("Just some prose.")]);
+    // Inline code isn't a fence
+    assert_eq!(parse(&["use `x` and ``y``"]), vec![text("use `x` and ``y``")]);
+    // Whitespace between blocks isn't worth a segment
+    assert_eq!(
+        parse(&["```a\n1```\n\n```b\n2```"]),
+        vec![block("a", "1"), block("b", "2")]
+    );
+}
+
+#[test]
+fn unknown_info_strings_are_still_blocks() {
+    // Whether a language is known is up to the registry, not the parser
+    assert_eq!(
+        parse(&["```python\nprint(1)\n```"]),
+        vec![block("python", "print(1)\n")]
+    );
+}
+
+#[test]
+fn invalid_info_strings_become_text() {
+    assert_eq!(
+        parse(&["```a`b"]),
+        vec![
+            Event::Error(ParseError::InvalidInfo("```a`".into())),
+            text("```a`b"),
+        ]
+    );
+
+    let long = "x".repeat(40);
+    let events = parse(&["```", &long]);
+    assert!(matches!(&events[0], Event::Error(ParseError::InvalidInfo(raw)) if raw.len() == 3 + 33));
+    assert_eq!(events[1], text(&format!("```{}", long)));
+}
+
+#[test]
+fn backticks_inside_json_strings() {
+    assert_eq!(
+        parse(&["```json\n{\"code\": \"```x```\"}\n```"]),
+        vec![block("json", "{\"code\": \"```x```\"}\n")]
+    );
+    // An escaped quote doesn't end the string
+    assert_eq!(
+        parse(&["```json\n{\"a\": \"\\\"```\"}", "```"]),
+        vec![block("json", "{\"a\": \"\\\"```\"}")]
+    );
+    // Only JSON gets this treatment
+    assert_eq!(
+        parse(&["```speech\nsay \"```\" now"]),
+        vec![block("speech", "say \""), text("\" now")]
+    );
+}
+
+#[test]
+fn unterminated_block_reported_by_finish() {
+    let mut parser = FenceParser::default();
+    assert!(parser.is_idle());
+    assert!(parser.push("```speech\nHello``").is_empty());
+    assert_eq!(parser.open_block(), Some(("speech", "Hello")));
+    assert!(!parser.is_idle());
+
+    assert_eq!(
+        parser.finish(),
+        vec![Event::Error(ParseError::Unterminated {
+            lang: "speech".into(),
+            content: "Hello``".into(),
+        })]
+    );
+    assert!(parser.is_idle());
+
+    assert_eq!(
+        parse(&["```json"]),
+        vec![Event::Error(ParseError::Unterminated {
+            lang: "json".into(),
+            content: String::new(),
+        })]
+    );
+}
@@ -0,0 +1,59 @@
+use actor_demo::history::{self, History, Overflow};
+use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs, Role};
+
+fn message(role: Role, content: &str) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessageArgs::default()
+        .role(role)
+        .content(content)
+        .build()
+        .unwrap()
+}
+
+fn contents(history: &History) -> Vec<&str> {
+    history
+        .messages()
+        .iter()
+        .map(|message| message.content.as_deref().unwrap_or_default())
+        .collect()
+}
+
+#[test]
+fn trim_keeps_system_prompt_and_latest_turn() {
+    let mut history = History::new(30, Overflow::Truncate);
+    history.push(message(Role::System, "You're a personal assistant."));
+    for i in 0..10 {
+        history.push(message(Role::User, &format!("Question number {}", i)));
+    }
+
+    let removed = history.trim();
+
+    assert!(history.tokens() <= 30);
+    assert!(!removed.is_empty());
+    assert_eq!(contents(&history)[0], "You're a personal assistant.");
+    assert_eq!(*contents(&history).last().unwrap(), "Question number 9");
+}
+
+#[test]
+fn summary_goes_after_system_prompt_and_is_trimmed_later() {
+    let mut history = History::new(40, Overflow::Summarise);
+    history.push(message(Role::System, "You're a personal assistant."));
+    history.push(message(Role::User, "Hello"));
+    history.insert_summary(history::summary_message("We said hi."));
+
+    assert_eq!(
+        contents(&history),
+        vec![
+            "You're a personal assistant.",
+            "Summary of the conversation so far: We said hi.",
+            "Hello"
+        ]
+    );
+
+    for i in 0..10 {
+        history.push(message(Role::User, &format!("Question number {}", i)));
+    }
+    history.trim();
+    assert!(!contents(&history)
+        .iter()
+        .any(|content| content.starts_with("Summary")));
+}
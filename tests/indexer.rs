@@ -0,0 +1,247 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use actix::prelude::*;
+use actor_demo::{
+    embedding::{EmbedBatch, EmbeddingStats, GetDimension, GetStats},
+    indexer::{Index, IndexReport, Indexer, PATH_FIELD},
+    keyword_index::{KeywordIndex, KeywordSearch},
+    vector_store::{Scroll, VectorDb, TEXT_FIELD},
+    vectordb_local::LocalStore,
+};
+use anyhow::Result;
+use tempfile::TempDir;
+
+mod common;
+
+use common::StubEmbedding;
+
+/// Takes a while over every batch, and remembers the most it had at once.
+struct SlowEmbedding {
+    busy: Arc<AtomicUsize>,
+    most: Arc<AtomicUsize>,
+}
+
+impl Actor for SlowEmbedding {
+    type Context = Context<Self>;
+}
+
+impl Handler<EmbedBatch> for SlowEmbedding {
+    type Result = ResponseFuture<Result<Vec<Vec<f32>>>>;
+
+    fn handle(&mut self, msg: EmbedBatch, _ctx: &mut Context<Self>) -> Self::Result {
+        let busy = self.busy.clone();
+        let most = self.most.clone();
+        Box::pin(async move {
+            let now = busy.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            busy.fetch_sub(1, Ordering::SeqCst);
+            Ok(msg.0.iter().map(|_| vec![1.0, 0.0]).collect())
+        })
+    }
+}
+
+impl Handler<GetDimension> for SlowEmbedding {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: GetDimension, _ctx: &mut Context<Self>) -> Self::Result {
+        2
+    }
+}
+
+impl Handler<GetStats> for SlowEmbedding {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _msg: GetStats, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(EmbeddingStats::default())
+    }
+}
+
+/// Writes the file with a modification time that can't be mistaken for the last one.
+fn write(path: &Path, text: &str, age: u64) {
+    fs::write(path, text).unwrap();
+    let modified = SystemTime::now() - Duration::from_secs(age);
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+}
+
+async fn index(indexer: &Addr<Indexer>, paths: &[&PathBuf]) -> Result<IndexReport> {
+    indexer
+        .send(Index {
+            paths: paths.iter().map(|path| path.to_path_buf()).collect(),
+            collection_name: "docs".into(),
+        })
+        .await
+        .unwrap()
+}
+
+/// The path and text of every point, sorted.
+async fn points(db: &Addr<VectorDb>) -> Vec<(String, String)> {
+    let mut points: Vec<(String, String)> = db
+        .send(Scroll {
+            collection_name: "docs".into(),
+            fields: vec![PATH_FIELD.into(), TEXT_FIELD.into()],
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|payload| {
+            let path = Path::new(payload[PATH_FIELD].as_str().unwrap());
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, payload[TEXT_FIELD].as_str().unwrap().trim().to_string())
+        })
+        .collect();
+    points.sort();
+    points
+}
+
+fn counts(report: &IndexReport) -> [usize; 4] {
+    [report.added, report.updated, report.removed, report.unchanged]
+}
+
+#[actix::test]
+async fn indexes_only_what_changed() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let docs = dir.join("docs");
+    fs::create_dir_all(&docs).unwrap();
+    let (a, b, c, d) = (docs.join("a.txt"), docs.join("b.txt"), docs.join("c.txt"), docs.join("d.txt"));
+    write(&a, "Alpha.", 60);
+    write(&b, "Beta.", 60);
+    write(&c, "Gamma.", 60);
+
+    let db = VectorDb::with(LocalStore::open(dir.join("vectors")).unwrap()).start();
+    let indexer = Indexer::with(StubEmbedding.start(), db.clone()).start();
+
+    let report = index(&indexer, &[&a, &b, &c]).await.unwrap();
+    assert_eq!(counts(&report), [3, 0, 0, 0]);
+    assert_eq!(report.chunks, 3);
+
+    let report = index(&indexer, &[&a, &b, &c]).await.unwrap();
+    assert_eq!(counts(&report), [0, 0, 0, 3]);
+    assert_eq!(report.chunks, 0);
+
+    // a is touched but not changed, b is changed, c is deleted and d is new
+    write(&a, "Alpha.", 30);
+    write(&b, "Beta, again.", 30);
+    fs::remove_file(&c).unwrap();
+    write(&d, "Delta.", 30);
+    let report = index(&indexer, &[&a, &b, &d]).await.unwrap();
+    assert_eq!(counts(&report), [1, 1, 1, 1]);
+    assert_eq!(report.chunks, 2);
+    assert_eq!(
+        points(&db).await,
+        vec![
+            ("a.txt".into(), "Alpha.".into()),
+            ("b.txt".into(), "Beta, again.".into()),
+            ("d.txt".into(), "Delta.".into()),
+        ]
+    );
+}
+
+#[actix::test]
+async fn failed_update_keeps_the_old_points() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let docs = dir.join("docs");
+    fs::create_dir_all(&docs).unwrap();
+    let (a, b) = (docs.join("a.txt"), docs.join("b.txt"));
+    write(&a, "Alpha.", 60);
+    write(&b, "Beta.", 60);
+
+    let db = VectorDb::with(LocalStore::open(dir.join("vectors")).unwrap()).start();
+    let indexer = Indexer::with(StubEmbedding.start(), db.clone()).start();
+    index(&indexer, &[&a, &b]).await.unwrap();
+
+    // b can't be embedded, and a is gone, but neither is dropped from the collection
+    write(&b, "Beta FAIL.", 30);
+    fs::remove_file(&a).unwrap();
+    assert!(index(&indexer, &[&b]).await.is_err());
+    assert_eq!(
+        points(&db).await,
+        vec![("a.txt".into(), "Alpha.".into()), ("b.txt".into(), "Beta.".into())]
+    );
+
+    // Once it can be, it's updated as usual
+    write(&b, "Beta, fixed.", 10);
+    let report = index(&indexer, &[&b]).await.unwrap();
+    assert_eq!(counts(&report), [0, 1, 1, 0]);
+    assert_eq!(points(&db).await, vec![("b.txt".into(), "Beta, fixed.".into())]);
+}
+
+#[actix::test]
+async fn keeps_only_a_few_batches_in_flight() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let docs = dir.join("docs");
+    fs::create_dir_all(&docs).unwrap();
+    let paths: Vec<PathBuf> = (0..6).map(|i| docs.join(format!("{}.txt", i))).collect();
+    for (i, path) in paths.iter().enumerate() {
+        write(path, &format!("Document {}.", i), 60);
+    }
+
+    let most = Arc::new(AtomicUsize::new(0));
+    let embedding = SlowEmbedding {
+        busy: Arc::default(),
+        most: most.clone(),
+    };
+    let db = VectorDb::with(LocalStore::open(dir.join("vectors")).unwrap()).start();
+    let indexer = Indexer::with(embedding.start(), db.clone())
+        .batch_size(1)
+        .in_flight(2)
+        .start();
+
+    let report = index(&indexer, &paths.iter().collect::<Vec<_>>()).await.unwrap();
+    assert_eq!(report.chunks, 6);
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+}
+
+#[actix::test]
+async fn fills_an_empty_keyword_index_from_the_collection() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let docs = dir.join("docs");
+    fs::create_dir_all(&docs).unwrap();
+    let (a, b) = (docs.join("a.txt"), docs.join("b.txt"));
+    write(&a, "Set max_retries to retry.", 60);
+    write(&b, "E0502 is a borrow error.", 60);
+
+    // Indexed before there was a keyword index
+    let db = VectorDb::with(LocalStore::open(dir.join("vectors")).unwrap()).start();
+    let indexer = Indexer::with(StubEmbedding.start(), db.clone()).start();
+    index(&indexer, &[&a, &b]).await.unwrap();
+
+    // Nothing changed, but the keyword index still gets the chunks
+    let keywords = KeywordIndex::open(dir.join("keywords")).unwrap().start();
+    let indexer = Indexer::with(StubEmbedding.start(), db.clone())
+        .keywords(keywords.clone())
+        .start();
+    let report = index(&indexer, &[&a, &b]).await.unwrap();
+    assert_eq!(counts(&report), [0, 0, 0, 2]);
+
+    let hits = keywords
+        .send(KeywordSearch {
+            collection_name: "docs".into(),
+            query: "max_retries".into(),
+            limit: 5,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].text.trim(), "Set max_retries to retry.");
+    assert!(hits[0].payload[PATH_FIELD].as_str().unwrap().ends_with("a.txt"));
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use actix::prelude::*;
+use actor_demo::{
+    code_writer::CodeWriter,
+    interpreter::{Action, Actions, GetObservations, Interpreter},
+    keyword_index::{AddChunks, KeywordChunk, KeywordIndex},
+    vector_store::{CollectionInfo, CreateCollection, Distance, Point, Upsert, VectorDb, TEXT_FIELD},
+    vectordb_local::LocalStore,
+};
+use tempfile::TempDir;
+
+mod common;
+
+use common::StubEmbedding;
+
+fn point(id: u64, vector: Vec<f32>, text: &str) -> Point {
+    Point {
+        id,
+        vector,
+        payload: HashMap::from([(TEXT_FIELD.to_string(), text.into())]),
+    }
+}
+
+async fn create(db: &Addr<VectorDb>, collection_name: &str) {
+    db.send(CreateCollection {
+        collection_name: collection_name.into(),
+        dimension: 2,
+        distance: Distance::Cosine,
+    })
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+async fn observe(interpreter: &Addr<Interpreter>, action: Action) -> String {
+    interpreter.send(Actions(vec![action])).await.unwrap().unwrap();
+    interpreter.send(GetObservations).await.unwrap().unwrap()
+}
+
+fn search(query: &str, collection: &str) -> Action {
+    Action::Search {
+        query: query.into(),
+        collection: collection.into(),
+    }
+}
+
+#[actix::test]
+async fn search_observations() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let db = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+    create(&db, "docs").await;
+    create(&db, "empty").await;
+    db.send(Upsert {
+        collection_name: "docs".into(),
+        points: vec![
+            point(1, vec![1.0, 0.0], "Cats purr."),
+            point(2, vec![0.0, 1.0], "Dogs bark."),
+            point(3, vec![0.7, 0.7], "Both are pets."),
+        ],
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    let interpreter = Interpreter::with()
+        .search(db, StubEmbedding.start())
+        .top_k(2)
+        .start();
+
+    // Best first, and no more than top_k
+    assert_eq!(
+        observe(&interpreter, search("cats", "docs")).await,
+        "Results in docs for \"cats\":\n[1] (score 1.00) Cats purr.\n[2] (score 0.71) Both are pets."
+    );
+    assert_eq!(
+        observe(&interpreter, search("dogs", "docs")).await,
+        "Results in docs for \"dogs\":\n[1] (score 1.00) Dogs bark.\n[2] (score 0.71) Both are pets."
+    );
+
+    assert_eq!(
+        observe(&interpreter, search("cats", "empty")).await,
+        "No results in empty for \"cats\"."
+    );
+
+    // Failures are passed on to the LLM rather than ending the turn
+    assert_eq!(
+        observe(&interpreter, search("cats", "missing")).await,
+        "Searching missing for \"cats\" failed: No collection named missing"
+    );
+    assert_eq!(
+        observe(&interpreter, search("birds", "docs")).await,
+        "Searching docs for \"birds\" failed: model fell over"
+    );
+}
+
+#[actix::test]
+async fn hybrid_search_falls_back_to_keywords() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let db = VectorDb::with(LocalStore::open(dir.join("vectors")).unwrap()).start();
+    create(&db, "docs").await;
+    let keywords = KeywordIndex::open(dir.join("keywords")).unwrap().start();
+    keywords
+        .send(AddChunks {
+            collection_name: "docs".into(),
+            chunks: vec![KeywordChunk {
+                id: 1,
+                text: "Birds sing.".into(),
+                payload: HashMap::new(),
+            }],
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let interpreter = Interpreter::with()
+        .search(db, StubEmbedding.start())
+        .keywords(keywords)
+        .start();
+
+    // The query can't be embedded, but the keywords still find it. Fused results
+    // don't show scores, as vector and keyword scores aren't comparable.
+    assert_eq!(
+        observe(&interpreter, search("birds", "docs")).await,
+        "Results in docs for \"birds\":\n[1] Birds sing."
+    );
+}
+
+#[actix::test]
+async fn search_without_a_store() {
+    let interpreter = Interpreter::with().start();
+    assert_eq!(
+        observe(&interpreter, search("cats", "docs")).await,
+        "Search is not available."
+    );
+}
+
+#[actix::test]
+async fn writes_files_when_asked() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let interpreter = Interpreter::with().writing(CodeWriter.start().recipient()).start();
+    let filename = dir.join("notes.txt").to_string_lossy().to_string();
+    let write = |filename: &str| Action::Writetofile {
+        filename: filename.into(),
+        content: "Birds sing.".into(),
+    };
+
+    assert_eq!(observe(&interpreter, write(&filename)).await, format!("Wrote {}.", filename));
+    assert_eq!(std::fs::read_to_string(&filename).unwrap(), "Birds sing.");
+
+    // A missing directory is reported rather than taking the writer down
+    let missing = dir.join("missing").join("notes.txt").to_string_lossy().to_string();
+    assert!(observe(&interpreter, write(&missing))
+        .await
+        .starts_with(&format!("Writing {} failed:", missing)));
+
+    assert_eq!(
+        observe(&Interpreter::with().start(), write(&filename)).await,
+        "Writing files is not available."
+    );
+}
+
+#[actix::test]
+async fn deleting_a_collection_needs_the_users_ok() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let db = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+    create(&db, "archive").await;
+
+    let answers = Arc::new(Mutex::new(VecDeque::from([false, true])));
+    let questions = Arc::new(Mutex::new(vec![]));
+    let interpreter = Interpreter::with()
+        .search(db.clone(), StubEmbedding.start())
+        .confirm({
+            let answers = answers.clone();
+            let questions = questions.clone();
+            move |question| {
+                questions.lock().unwrap().push(question.to_string());
+                answers.lock().unwrap().pop_front().unwrap()
+            }
+        })
+        .start();
+    let delete = || Action::DeleteCollection { name: "archive".into() };
+    let exists = || db.send(CollectionInfo { collection_name: "archive".into() });
+
+    assert_eq!(
+        observe(&interpreter, delete()).await,
+        "The user didn't confirm, so archive was not deleted."
+    );
+    assert!(exists().await.unwrap().is_ok());
+
+    assert_eq!(observe(&interpreter, delete()).await, "Deleted archive.");
+    assert!(exists().await.unwrap().is_err());
+    assert_eq!(
+        *questions.lock().unwrap(),
+        vec!["Delete the collection archive and everything in it?"; 2]
+    );
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use actor_demo::{
+    keyword_index::{
+        AddChunks, DropCollection, Flush, Fusion, KeywordChunk, KeywordIndex, KeywordSearch, RemoveWhere,
+    },
+    vector_store::SearchHit,
+};
+use tempfile::TempDir;
+
+fn chunk(id: u64, path: &str, text: &str) -> KeywordChunk {
+    KeywordChunk {
+        id,
+        text: text.into(),
+        payload: HashMap::from([("path".to_string(), path.into())]),
+    }
+}
+
+fn hit(path: &str, text: &str) -> SearchHit {
+    SearchHit {
+        text: text.into(),
+        score: 0.0,
+        payload: HashMap::from([("path".to_string(), path.into())]),
+    }
+}
+
+async fn search(index: &Addr<KeywordIndex>, query: &str) -> Vec<String> {
+    index
+        .send(KeywordSearch {
+            collection_name: "docs".into(),
+            query: query.into(),
+            limit: 5,
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|hit| hit.text)
+        .collect()
+}
+
+#[actix::test]
+async fn finds_exact_identifiers_and_survives_a_restart() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let index = KeywordIndex::open(dir).unwrap().start();
+    index
+        .send(AddChunks {
+            collection_name: "docs".into(),
+            chunks: vec![
+                chunk(1, "errors.md", "E0502 means the value is borrowed as mutable and immutable."),
+                chunk(2, "config.md", "Set max_retries to retry failed requests."),
+                chunk(3, "intro.md", "The assistant answers questions about your documents."),
+            ],
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let hits = search(&index, "what is e0502?").await;
+    assert_eq!(hits[0], "E0502 means the value is borrowed as mutable and immutable.");
+    assert_eq!(search(&index, "max_retries").await, vec!["Set max_retries to retry failed requests."]);
+
+    index
+        .send(RemoveWhere {
+            collection_name: "docs".into(),
+            field: "path".into(),
+            values: vec!["config.md".into()],
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Nothing is written until it's flushed
+    let unflushed = KeywordIndex::open(dir).unwrap().start();
+    assert!(search(&unflushed, "E0502").await.is_empty());
+
+    index.send(Flush).await.unwrap().unwrap();
+    let reopened = KeywordIndex::open(dir).unwrap().start();
+    assert!(search(&reopened, "max_retries").await.is_empty());
+    assert_eq!(search(&reopened, "E0502").await.len(), 1);
+}
+
+#[actix::test]
+async fn collection_names_cant_escape_the_directory() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let index = KeywordIndex::open(dir.join("keywords")).unwrap().start();
+
+    for collection_name in ["../escaped", "a/b", ""] {
+        let added = index
+            .send(AddChunks {
+                collection_name: collection_name.into(),
+                chunks: vec![chunk(1, "a.md", "alpha")],
+            })
+            .await
+            .unwrap();
+        assert!(added.is_err(), "{:?} was allowed", collection_name);
+
+        let searched = index
+            .send(KeywordSearch {
+                collection_name: collection_name.into(),
+                query: "alpha".into(),
+                limit: 5,
+            })
+            .await
+            .unwrap();
+        assert!(searched.is_err());
+        assert!(index.send(DropCollection(collection_name.into())).await.unwrap().is_err());
+    }
+    index.send(Flush).await.unwrap().unwrap();
+    assert!(!dir.join("escaped.json").exists());
+}
+
+#[test]
+fn fusion_favours_hits_both_searches_agree_on() {
+    let vector = vec![hit("a.md", "alpha"), hit("b.md", "beta"), hit("c.md", "gamma")];
+    let keyword = vec![hit("c.md", "gamma"), hit("d.md", "delta")];
+
+    let fused = Fusion::default().fuse(vector.clone(), keyword.clone(), 2);
+    let texts: Vec<&str> = fused.iter().map(|hit| hit.text.as_str()).collect();
+    assert_eq!(texts, vec!["gamma", "alpha"]);
+
+    // Weighting keywords up puts their best hit first
+    let keyword_heavy = Fusion {
+        vector: 0.2,
+        ..Fusion::default()
+    };
+    let fused = keyword_heavy.fuse(vector, keyword, 2);
+    let texts: Vec<&str> = fused.iter().map(|hit| hit.text.as_str()).collect();
+    assert_eq!(texts, vec!["gamma", "delta"]);
+}
+
+#[test]
+fn fused_hits_keep_their_own_scores() {
+    let scored = |path: &str, text: &str, score: f32| SearchHit { score, ..hit(path, text) };
+    let vector = vec![scored("a.md", "alpha", 0.9), scored("c.md", "gamma", 0.7)];
+    let keyword = vec![scored("c.md", "gamma", 4.2), scored("d.md", "delta", 3.1)];
+
+    let fused = Fusion::default().fuse(vector, keyword, 3);
+    let scores: Vec<(&str, f32)> = fused.iter().map(|hit| (hit.text.as_str(), hit.score)).collect();
+    // Gamma comes first for being in both, with its vector score
+    assert_eq!(scores, vec![("gamma", 0.7), ("alpha", 0.9), ("delta", 3.1)]);
+}
@@ -0,0 +1,38 @@
+use std::path::Path;
+
+use actor_demo::loader::{loader_for, Location, Loader, MarkdownLoader};
+
+#[test]
+fn markdown_is_split_by_heading() {
+    let markdown = "Intro text\n\n# Notes\n\nSome notes.\n\n## Setup\n\n```sh\n# not a heading\n```\n\n# Other\n\nMore.\n";
+
+    let parts = MarkdownLoader.load(markdown.as_bytes()).unwrap();
+
+    let locations: Vec<_> = parts.iter().map(|part| part.location.clone()).collect();
+    assert_eq!(
+        locations,
+        vec![
+            None,
+            Some(Location::Section("Notes".into())),
+            Some(Location::Section("Notes > Setup".into())),
+            Some(Location::Section("Other".into())),
+        ]
+    );
+    assert!(parts[2].text.contains("# not a heading"));
+    assert!(parts.iter().all(|part| part.title.as_deref() == Some("Notes")));
+}
+
+#[test]
+fn unknown_extensions_are_sniffed() {
+    let html = b"<!DOCTYPE html><html><head><title>Hello</title></head><body><p>World</p></body></html>";
+    let parts = loader_for(Path::new("page"), html).unwrap().load(html).unwrap();
+    assert_eq!(parts[0].title.as_deref(), Some("Hello"));
+    assert!(parts[0].text.contains("World"));
+    assert!(!parts[0].text.contains("<p>"));
+
+    let text = b"Just some text";
+    let parts = loader_for(Path::new("notes.log"), text).unwrap().load(text).unwrap();
+    assert_eq!(parts[0].text, "Just some text");
+
+    assert!(loader_for(Path::new("image.bin"), &[0xff, 0xfe, 0x00]).is_err());
+}
@@ -0,0 +1,233 @@
+use actix::prelude::*;
+use actor_demo::{
+    audio_player::Cancel,
+    chat_backend::{MockBackend, ScriptedReply},
+    citation::Citations,
+    interpreter::{Actions, Text},
+    llm::{ChatMessage, LlmActor},
+    token_processor::TokenProcessorActor,
+    tts_polly::Utterance,
+};
+use anyhow::Result;
+use async_openai::types::Role;
+use futures::channel::oneshot;
+
+/// Stands in for TTS and the interpreter, and remembers what it was sent.
+#[derive(Default)]
+struct Recorder {
+    utterances: Vec<String>,
+    actions: Vec<String>,
+    /// Told when the first utterance arrives
+    first_utterance: Option<oneshot::Sender<()>>,
+}
+
+impl Actor for Recorder {
+    type Context = Context<Self>;
+}
+
+impl Handler<Utterance> for Recorder {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: Utterance, _ctx: &mut Context<Self>) -> Self::Result {
+        self.utterances.push(msg.0);
+        if let Some(first_utterance) = self.first_utterance.take() {
+            let _ = first_utterance.send(());
+        }
+        Ok(())
+    }
+}
+
+impl Handler<Text> for Recorder {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: Text, _ctx: &mut Context<Self>) -> Self::Result {
+        self.actions.push(msg.0);
+        Ok(())
+    }
+}
+
+impl Handler<Actions> for Recorder {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: Actions, _ctx: &mut Context<Self>) -> Self::Result {
+        self.actions
+            .extend(msg.0.iter().map(|action| format!("{:?}", action)));
+        Ok(())
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "(Vec<String>, Vec<String>)")]
+struct Recorded;
+
+impl Handler<Recorded> for Recorder {
+    type Result = MessageResult<Recorded>;
+
+    fn handle(&mut self, _msg: Recorded, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult((
+            std::mem::take(&mut self.utterances),
+            std::mem::take(&mut self.actions),
+        ))
+    }
+}
+
+fn fixture(name: &str) -> MockBackend {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    MockBackend::from_file(path).unwrap()
+}
+
+async fn say(llm: &Addr<LlmActor>, text: &str) {
+    llm.send(ChatMessage(text.into(), Role::User))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[actix::test]
+async fn fenced_blocks_split_across_tokens() {
+    let recorder = Recorder::default().start();
+    let token_proc = TokenProcessorActor::default()
+        .route("speech", recorder.clone().recipient::<Utterance>())
+        .route("json", recorder.clone().recipient::<Text>())
+        .start();
+    let llm = LlmActor::with_backend(token_proc, fixture("greeting.json")).start();
+
+    say(&llm, "hi").await;
+    let (utterances, actions) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["Hello there.\n"]);
+    assert_eq!(
+        actions,
+        vec![
+            "{\"actions\": [{\"type\": \"getstdinput\", \"prompt\": \"What's the `path`?\"}]}\n"
+        ]
+    );
+
+    say(&llm, "and?").await;
+    let (utterances, actions) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["Dr. Smith measured 3.14 metres. That's it!\n"]);
+    assert!(actions.is_empty());
+}
+
+#[actix::test]
+async fn speech_streamed_by_sentence() {
+    let recorder = Recorder::default().start();
+    let token_proc = TokenProcessorActor::default()
+        .route("speech", recorder.clone().recipient::<Utterance>())
+        .route("json", recorder.clone().recipient::<Text>())
+        .stream_sentences("speech")
+        .start();
+    let llm = LlmActor::with_backend(token_proc, fixture("greeting.json")).start();
+
+    say(&llm, "hi").await;
+    let (utterances, _) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["Hello there."]);
+
+    say(&llm, "and?").await;
+    let (utterances, _) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(
+        utterances,
+        vec!["Dr. Smith measured 3.14 metres.", "That's it!"]
+    );
+}
+
+#[actix::test]
+async fn citation_markers_are_not_spoken() {
+    let recorder = Recorder::default().start();
+    let token_proc = TokenProcessorActor::default()
+        .route("speech", recorder.clone().recipient::<Utterance>())
+        .stream_sentences("speech")
+        .citations("speech", Citations::default())
+        .start();
+    let reply = "```speech\nThe sky is blue [c0a1b2c3d]. [c00000001] It rains. [c00000002]\n```";
+    let reply = reply.chars().map(String::from).collect();
+    let llm = LlmActor::with_backend(token_proc, MockBackend::new(vec![reply])).start();
+
+    // Markers that end up on their own aren't sent to TTS as empty utterances
+    say(&llm, "why?").await;
+    let (utterances, _) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["The sky is blue.", "It rains."]);
+}
+
+#[actix::test]
+async fn function_calls_go_to_interpreter() {
+    let recorder = Recorder::default().start();
+    let token_proc = TokenProcessorActor::default()
+        .route("speech", recorder.clone().recipient::<Utterance>())
+        .start();
+    let llm = LlmActor::with_backend(token_proc, fixture("function_call.json"))
+        .functions(recorder.clone().recipient::<Actions>())
+        .start();
+
+    say(&llm, "what is k-NN?").await;
+    let (utterances, actions) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["Let me search for that.\n"]);
+    assert_eq!(
+        actions,
+        vec![
+            "Search { query: \"what is k-NN?\", collection: \"machine_learning\" }"
+        ]
+    );
+}
+
+#[actix::test]
+async fn running_out_of_replies_is_an_error() {
+    let token_proc = TokenProcessorActor::default().start();
+    let llm = LlmActor::with_backend(token_proc, MockBackend::new(vec![])).start();
+
+    let result = llm.send(ChatMessage("hi".into(), Role::User)).await.unwrap();
+    assert!(result.is_err());
+}
+
+#[actix::test]
+async fn cancel_stops_the_reply() {
+    let (first_utterance, spoken) = oneshot::channel();
+    let recorder = Recorder {
+        first_utterance: Some(first_utterance),
+        ..Default::default()
+    }
+    .start();
+    let token_proc = TokenProcessorActor::default()
+        .route("speech", recorder.clone().recipient::<Utterance>())
+        .start();
+    let llm = LlmActor::with_backend(token_proc, fixture("long_reply.json")).start();
+
+    // Cancel as soon as the first block has been spoken, while the second is still streaming
+    let reply = llm.send(ChatMessage("talk to me".into(), Role::User));
+    let cancel = async {
+        spoken.await.unwrap();
+        llm.send(Cancel).await.unwrap();
+    };
+    let (result, _) = futures::join!(reply, cancel);
+    result.unwrap().unwrap();
+
+    let (utterances, _) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["First.\n"]);
+}
+
+#[actix::test]
+async fn a_broken_stream_doesnt_spoil_the_next_reply() {
+    let recorder = Recorder::default().start();
+    let token_proc = TokenProcessorActor::default()
+        .route("speech", recorder.clone().recipient::<Utterance>())
+        .start();
+    let backend = MockBackend::scripted(vec![
+        ScriptedReply {
+            tokens: vec!["```speech\n".into(), "Half a".into()],
+            error: Some("connection reset".into()),
+            ..Default::default()
+        },
+        ScriptedReply {
+            tokens: vec!["```speech\nWhole.\n```".into()],
+            ..Default::default()
+        },
+    ]);
+    let llm = LlmActor::with_backend(token_proc, backend).start();
+
+    let result = llm.send(ChatMessage("hi".into(), Role::User)).await.unwrap();
+    assert_eq!(result.unwrap_err().to_string(), "connection reset");
+
+    // The half-open block was dropped rather than carried into this one
+    say(&llm, "again").await;
+    let (utterances, _) = recorder.send(Recorded).await.unwrap();
+    assert_eq!(utterances, vec!["Whole.\n"]);
+}
@@ -0,0 +1,85 @@
+use actor_demo::sentence::{complete_sentences, next_boundary};
+
+fn sentences(text: &str) -> Vec<&str> {
+    complete_sentences(text).0
+}
+
+#[test]
+fn splits_on_final_punctuation() {
+    assert_eq!(
+        sentences("Hello there! How are you? I'm fine... Thanks. "),
+        vec!["Hello there!", "How are you?", "I'm fine...", "Thanks."]
+    );
+    assert_eq!(sentences("He said \"stop.\" Then left. "), vec!["He said \"stop.\"", "Then left."]);
+}
+
+#[test]
+fn abbreviations() {
+    assert_eq!(
+        sentences("Dr. Smith met Mr. Jones at 5 p.m. on Main St. and left. Next "),
+        vec!["Dr. Smith met Mr. Jones at 5 p.m. on Main St. and left."]
+    );
+    assert_eq!(
+        sentences("Fruit, e.g. apples, i.e. the red ones. Done. "),
+        vec!["Fruit, e.g. apples, i.e. the red ones.", "Done."]
+    );
+    assert_eq!(
+        sentences("She moved to the U.S. last year. Then the U.K. too. "),
+        vec!["She moved to the U.S. last year.", "Then the U.K. too."]
+    );
+}
+
+#[test]
+fn no_and_co_are_only_abbreviations_sometimes() {
+    assert_eq!(sentences("I said no. Then we left. "), vec!["I said no.", "Then we left."]);
+    assert_eq!(
+        sentences("Room No. 5 is free. Go. "),
+        vec!["Room No. 5 is free.", "Go."]
+    );
+    assert_eq!(
+        sentences("Acme Co. makes them. Buy one. "),
+        vec!["Acme Co. makes them.", "Buy one."]
+    );
+
+    // Whether "no." ends the sentence isn't known until the next word arrives
+    assert_eq!(next_boundary("I said no. "), None);
+    assert_eq!(next_boundary("I said no. Then"), Some(10));
+    assert_eq!(next_boundary("Room No. 5"), None);
+}
+
+#[test]
+fn initials() {
+    assert_eq!(
+        sentences("J. R. R. Tolkien wrote it. Read it. "),
+        vec!["J. R. R. Tolkien wrote it.", "Read it."]
+    );
+    // "I" is a word, not an initial
+    assert_eq!(sentences("So do I. Really. "), vec!["So do I.", "Really."]);
+}
+
+#[test]
+fn decimals_and_urls() {
+    assert_eq!(
+        sentences("Pi is 3.14 roughly. See example.com/a.b for more. "),
+        vec!["Pi is 3.14 roughly.", "See example.com/a.b for more."]
+    );
+    // Still streaming, so "3." may turn out to be "3.14"
+    assert_eq!(next_boundary("It costs 3."), None);
+    assert_eq!(next_boundary("Go to example."), None);
+}
+
+#[test]
+fn trailing_fragments_are_left_over() {
+    let text = "One. Two is still";
+    let (done, offset) = complete_sentences(text);
+    assert_eq!(done, vec!["One."]);
+    assert_eq!(&text[offset..], " Two is still");
+
+    assert_eq!(complete_sentences("no punctuation yet"), (vec![], 0));
+}
+
+#[test]
+fn blank_lines_end_sentences() {
+    assert_eq!(sentences("A heading\n\nBody text. "), vec!["A heading", "Body text."]);
+    assert_eq!(next_boundary("\n\nText"), None);
+}
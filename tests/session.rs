@@ -0,0 +1,56 @@
+use actix::prelude::*;
+use actor_demo::{
+    citation::Source,
+    interpreter::Ting,
+    session::{Entry, Record, SessionStore},
+};
+use async_openai::types::Role;
+use tempfile::TempDir;
+
+#[actix::test]
+async fn recorded_session_can_be_resumed() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let name = SessionStore::new_session_name();
+    let store = SessionStore::open(dir, &name).unwrap().start();
+
+    for (role, content) in [(Role::System, "You're a personal assistant."), (Role::User, "Hi")] {
+        store.do_send(Record(Entry::Message {
+            role,
+            content: content.into(),
+            name: None,
+            function_call: None,
+        }));
+    }
+    store.do_send(Record(Entry::Interpreter {
+        memory: vec![Ting::Input("notes/".into())],
+        observations: vec![],
+    }));
+    let source = Source {
+        path: "notes/a.md".into(),
+        document: "notes/a.md".into(),
+        location: None,
+        start: 0,
+        end: 120,
+        score: 0.8,
+    };
+    store.do_send(Record(Entry::Citation { source: source.clone() }));
+    store
+        .send(Record(Entry::Interpreter {
+            memory: vec![Ting::Documents(vec!["notes/a.md".into()])],
+            observations: vec!["Indexed 1 document".into()],
+        }))
+        .await
+        .unwrap();
+
+    let session = SessionStore::load(dir, &name).unwrap();
+    let contents: Vec<_> = session
+        .messages
+        .iter()
+        .map(|message| message.content.clone().unwrap_or_default())
+        .collect();
+    assert_eq!(contents, vec!["You're a personal assistant.", "Hi"]);
+    assert!(matches!(session.memory.as_slice(), [Ting::Documents(docs)] if docs == &["notes/a.md"]));
+    assert_eq!(session.observations, vec!["Indexed 1 document"]);
+    assert_eq!(session.citations, vec![source]);
+}
@@ -0,0 +1,52 @@
+use actix::prelude::*;
+use actor_demo::{
+    audio_player::{Activity, StatusRequest},
+    chat_backend::{MockBackend, ScriptedReply},
+    llm::{ChatMessage, LlmActor},
+    token_processor::TokenProcessorActor,
+};
+use async_openai::types::Role;
+
+#[test]
+fn activity_tracks_jobs_until_they_finish() {
+    let mut activity = Activity::default();
+    assert!(activity.status().is_idle());
+
+    let first = activity.start("first");
+    let second = activity.start("second");
+    let status = activity.status();
+    assert_eq!(status.queue_depth, 2);
+    assert_eq!(status.current_job.as_deref(), Some("first"));
+
+    // Jobs can finish out of order
+    activity.finish(second);
+    assert_eq!(activity.status().current_job.as_deref(), Some("first"));
+
+    activity.finish(first);
+    let status = activity.status();
+    assert!(status.is_idle());
+    assert_eq!(status.current_job, None);
+}
+
+#[actix::test]
+async fn an_actor_reports_a_reply_while_its_in_flight() {
+    let token_proc = TokenProcessorActor::default().start();
+    let backend = MockBackend::scripted(vec![ScriptedReply {
+        tokens: vec!["```speech\n".into(), "Hello.".into(), "\n```".into()],
+        delay_ms: 20,
+        ..Default::default()
+    }]);
+    let llm = LlmActor::with_backend(token_proc, backend).start();
+    assert!(llm.send(StatusRequest).await.unwrap().unwrap().is_idle());
+
+    // Messages are handled in order, so the reply has started by the time the status is asked for
+    let reply = llm.send(ChatMessage("hi".into(), Role::User));
+    let status = llm.send(StatusRequest).await.unwrap().unwrap();
+    assert_eq!(status.queue_depth, 1);
+    assert_eq!(status.current_job.as_deref(), Some("Replying to User"));
+
+    reply.await.unwrap().unwrap();
+    let status = llm.send(StatusRequest).await.unwrap().unwrap();
+    assert!(status.is_idle());
+    assert_eq!(status.current_job, None);
+}
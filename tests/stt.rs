@@ -0,0 +1,54 @@
+use actor_demo::stt::{is_speech, rms, BargeInDetector, BARGE_IN_DURATION, VAD_FRAME};
+
+const FRAMES_NEEDED: usize = (BARGE_IN_DURATION.as_millis() / 20) as usize;
+
+fn frame(amplitude: f32) -> Vec<f32> {
+    // A square wave, so the RMS is exactly the amplitude
+    (0..VAD_FRAME)
+        .map(|i| if i % 2 == 0 { amplitude } else { -amplitude })
+        .collect()
+}
+
+#[test]
+fn rms_of_a_frame() {
+    assert_eq!(rms(&[]), 0.0);
+    assert!((rms(&frame(0.1)) - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn threshold_rises_with_playback() {
+    assert!(!is_speech(&frame(0.01), 0.0));
+    assert!(is_speech(&frame(0.1), 0.0));
+    // The same voice could just be the assistant's echo when it's playing loudly
+    assert!(!is_speech(&frame(0.1), 0.3));
+    assert!(is_speech(&frame(0.2), 0.3));
+}
+
+#[test]
+fn needs_sustained_speech() {
+    let mut detector = BargeInDetector::default();
+    for _ in 1..FRAMES_NEEDED {
+        assert!(!detector.push_frame(&frame(0.1), 0.0));
+    }
+    assert!(detector.push_frame(&frame(0.1), 0.0));
+}
+
+#[test]
+fn quiet_frames_undo_speech() {
+    let mut detector = BargeInDetector::default();
+    for _ in 1..FRAMES_NEEDED {
+        detector.push_frame(&frame(0.1), 0.0);
+    }
+    // A cough followed by silence isn't a barge-in
+    detector.push_frame(&frame(0.0), 0.0);
+    assert!(!detector.push_frame(&frame(0.1), 0.0));
+    assert!(detector.push_frame(&frame(0.1), 0.0));
+}
+
+#[test]
+fn echo_alone_never_barges_in() {
+    let mut detector = BargeInDetector::default();
+    for _ in 0..FRAMES_NEEDED * 4 {
+        assert!(!detector.push_frame(&frame(0.15), 0.4));
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use actor_demo::{
+    vector_store::{
+        CollectionInfo, Condition, CreateCollection, DeleteCollection, DeleteWhere, Distance,
+        EnsureCollection, Filter, Flush, ListCollections, Point, Range, Scroll, SearchRequest, Upsert, VectorDb,
+        TEXT_FIELD,
+    },
+    vectordb_local::LocalStore,
+};
+use tempfile::TempDir;
+
+fn point(id: u64, vector: Vec<f32>, path: &str, text: &str) -> Point {
+    Point {
+        id,
+        vector,
+        payload: HashMap::from([
+            ("path".to_string(), path.into()),
+            (TEXT_FIELD.to_string(), text.into()),
+        ]),
+    }
+}
+
+async fn search(db: &Addr<VectorDb>, vector: Vec<f32>) -> Vec<String> {
+    db.send(SearchRequest {
+        collection_name: "docs".into(),
+        vector,
+        limit: 2,
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+    .unwrap()
+    .into_iter()
+    .map(|hit| hit.text)
+    .collect()
+}
+
+#[actix::test]
+async fn local_store_searches_and_persists() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let db = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+
+    // Nothing there yet
+    let payloads = db
+        .send(Scroll {
+            collection_name: "docs".into(),
+            fields: vec!["path".into()],
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(payloads.is_empty());
+
+    db.send(EnsureCollection {
+        collection_name: "docs".into(),
+        dimension: 3,
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    db.send(Upsert {
+        collection_name: "docs".into(),
+        points: vec![
+            point(1, vec![1.0, 0.0, 0.0], "a.md", "alpha"),
+            point(2, vec![0.0, 1.0, 0.0], "b.md", "beta"),
+            point(3, vec![0.7, 0.7, 0.0], "c.md", "gamma"),
+        ],
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(search(&db, vec![2.0, 0.1, 0.0]).await, vec!["alpha", "gamma"]);
+
+    // One wrong dimension rejects the whole batch
+    let wrong = db
+        .send(Upsert {
+            collection_name: "docs".into(),
+            points: vec![
+                point(4, vec![1.0, 0.0, 0.0], "d.md", "delta"),
+                point(5, vec![1.0, 0.0], "e.md", "epsilon"),
+            ],
+        })
+        .await
+        .unwrap();
+    assert!(wrong.is_err());
+    assert_eq!(search(&db, vec![2.0, 0.1, 0.0]).await, vec!["alpha", "gamma"]);
+
+    db.send(DeleteWhere {
+        collection_name: "docs".into(),
+        field: "path".into(),
+        values: vec!["a.md".into()],
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    // Changes stay in memory until they're flushed
+    let unflushed = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+    let details = unflushed
+        .send(CollectionInfo {
+            collection_name: "docs".into(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(details.points, 0);
+
+    db.send(Flush).await.unwrap().unwrap();
+    let reopened = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+    assert_eq!(search(&reopened, vec![2.0, 0.1, 0.0]).await, vec!["gamma", "beta"]);
+    let payloads = reopened
+        .send(Scroll {
+            collection_name: "docs".into(),
+            fields: vec!["path".into()],
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(payloads.len(), 2);
+    assert!(payloads.iter().all(|payload| payload.len() == 1));
+}
+
+#[test]
+fn filters_combine_must_should_and_must_not() {
+    let payload = HashMap::from([
+        ("path".to_string(), "notes/rust.md".into()),
+        ("year".to_string(), 2021.into()),
+        ("tags".to_string(), serde_json::json!(["rust", "async"])),
+        ("draft".to_string(), false.into()),
+    ]);
+    let recent = Condition::Range {
+        field: "year".into(),
+        range: Range {
+            gte: Some(2020.0),
+            ..Default::default()
+        },
+    };
+
+    let filter = Filter::default()
+        .must(recent.clone())
+        .should(Condition::keyword("tags", "python"))
+        .should(Condition::keyword("tags", "async"))
+        .must_not(Condition::Match {
+            field: "draft".into(),
+            value: true.into(),
+        });
+    assert!(filter.matches(&payload));
+
+    assert!(!Filter::default().must_not(recent).matches(&payload));
+    assert!(!Filter::default().should(Condition::keyword("tags", "go")).matches(&payload));
+    assert!(!Filter::default().must(Condition::keyword("missing", "x")).matches(&payload));
+    assert!(Filter::any_of("path", &["a.md".into(), "notes/rust.md".into()]).matches(&payload));
+
+    // Numbers match by value, whichever way they were written
+    let year = |value: serde_json::Value| Condition::Match {
+        field: "year".into(),
+        value,
+    };
+    assert!(Filter::default().must(year(2021.into())).matches(&payload));
+    assert!(Filter::default().must(year(2021.0.into())).matches(&payload));
+    assert!(!Filter::default().must(year("2021".into())).matches(&payload));
+    assert!(!Filter::default().must(year(serde_json::Value::Null)).matches(&payload));
+}
+
+#[actix::test]
+async fn local_search_applies_filter_threshold_and_fields() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let db = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+    db.send(EnsureCollection {
+        collection_name: "docs".into(),
+        dimension: 2,
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    let points = (0..4)
+        .map(|i| {
+            let mut point = point(i, vec![1.0, i as f32], &format!("{}.md", i), &format!("chunk {}", i));
+            point.payload.insert("page".into(), i.into());
+            point
+        })
+        .collect();
+    db.send(Upsert {
+        collection_name: "docs".into(),
+        points,
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    let hits = db
+        .send(SearchRequest {
+            collection_name: "docs".into(),
+            vector: vec![1.0, 0.0],
+            limit: 10,
+            // Cosine similarity of [1, 2] and [1, 3] is below this
+            score_threshold: Some(0.5),
+            fields: Some(vec!["page".into()]),
+            filter: Some(Filter::default().must_not(Condition::keyword("path", "0.md"))),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let texts: Vec<&str> = hits.iter().map(|hit| hit.text.as_str()).collect();
+    assert_eq!(texts, vec!["chunk 1"]);
+    assert_eq!(hits[0].payload.len(), 1);
+    assert_eq!(hits[0].payload["page"], 1);
+
+    let hits = db
+        .send(SearchRequest {
+            collection_name: "docs".into(),
+            vector: vec![1.0, 0.0],
+            limit: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(hits.len(), 4);
+    assert!(hits.windows(2).all(|pair| pair[0].score >= pair[1].score));
+}
+
+#[actix::test]
+async fn collections_can_be_created_inspected_and_dropped() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    let db = VectorDb::with(LocalStore::open(dir).unwrap()).start();
+    let create = |name: &str| CreateCollection {
+        collection_name: name.into(),
+        dimension: 2,
+        distance: Distance::Euclid,
+    };
+
+    db.send(create("project-x")).await.unwrap().unwrap();
+    db.send(create("archive")).await.unwrap().unwrap();
+    assert!(db.send(create("project-x")).await.unwrap().is_err());
+    assert!(db.send(create("../escape")).await.unwrap().is_err());
+    assert_eq!(db.send(ListCollections).await.unwrap().unwrap(), vec!["archive", "project-x"]);
+
+    // Documents can't be indexed into a collection that isn't compared by cosine
+    let ensure = db
+        .send(EnsureCollection {
+            collection_name: "archive".into(),
+            dimension: 2,
+        })
+        .await
+        .unwrap();
+    assert!(ensure.is_err());
+
+    db.send(Upsert {
+        collection_name: "project-x".into(),
+        points: vec![
+            point(1, vec![0.0, 0.0], "a.md", "origin"),
+            point(2, vec![3.0, 4.0], "b.md", "far"),
+        ],
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    let details = db
+        .send(CollectionInfo {
+            collection_name: "project-x".into(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((details.points, details.dimension, details.distance), (2, 2, Distance::Euclid));
+
+    // Euclidean scores are distances, nearest first
+    let hits = db
+        .send(SearchRequest {
+            collection_name: "project-x".into(),
+            vector: vec![3.0, 4.0],
+            limit: 2,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(hits[0].text, "far");
+    assert_eq!(hits[1].score, 5.0);
+
+    db.send(DeleteCollection {
+        collection_name: "project-x".into(),
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(db.send(ListCollections).await.unwrap().unwrap(), vec!["archive"]);
+    assert!(db
+        .send(CollectionInfo {
+            collection_name: "project-x".into(),
+        })
+        .await
+        .unwrap()
+        .is_err());
+}
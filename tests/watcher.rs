@@ -0,0 +1,138 @@
+use std::{sync::Arc, time::Duration};
+
+use actix::prelude::*;
+use actor_demo::{
+    indexer::{Index, IndexReport},
+    watcher::{Changed, Unwatch, Watch, Watcher},
+};
+use anyhow::Result;
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    StreamExt,
+};
+use tempfile::TempDir;
+use tokio::{sync::Semaphore, time::timeout};
+
+const DEBOUNCE: Duration = Duration::from_millis(50);
+/// Long enough for anything that was going to happen to have happened
+const QUIET: Duration = Duration::from_millis(300);
+
+/// Stands in for the indexer. Reports each index as it starts, and holds
+/// it until the test releases it.
+struct Recorder {
+    started: UnboundedSender<String>,
+    release: Arc<Semaphore>,
+}
+
+impl Actor for Recorder {
+    type Context = Context<Self>;
+}
+
+impl Handler<Index> for Recorder {
+    type Result = ResponseFuture<Result<IndexReport>>;
+
+    fn handle(&mut self, msg: Index, _ctx: &mut Context<Self>) -> Self::Result {
+        let _ = self.started.unbounded_send(msg.collection_name.clone());
+        let release = self.release.clone();
+        Box::pin(async move {
+            release.acquire().await.unwrap().forget();
+            Ok(IndexReport {
+                collection_name: msg.collection_name,
+                ..Default::default()
+            })
+        })
+    }
+}
+
+struct Fixture {
+    watcher: Addr<Watcher>,
+    started: UnboundedReceiver<String>,
+    release: Arc<Semaphore>,
+    root: TempDir,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        let root = TempDir::new().unwrap();
+
+        let (started_tx, started) = mpsc::unbounded();
+        let release = Arc::new(Semaphore::new(0));
+        let recorder = Recorder {
+            started: started_tx,
+            release: release.clone(),
+        }
+        .start();
+        let watcher = Watcher::with(recorder.recipient()).debounce(DEBOUNCE).start();
+        watcher
+            .send(Watch {
+                root: root.path().to_path_buf(),
+                pattern: "*.md".into(),
+                collection_name: "notes".into(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        Self {
+            watcher,
+            started,
+            release,
+            root,
+        }
+    }
+
+    fn change(&self) {
+        self.watcher.do_send(Changed(self.root.path().to_path_buf()));
+    }
+
+    /// Whether an index starts within `wait`.
+    async fn index_started(&mut self, wait: Duration) -> bool {
+        timeout(wait, self.started.next()).await.is_ok()
+    }
+}
+
+#[actix::test]
+async fn bursts_of_changes_index_once() {
+    let mut fixture = Fixture::new().await;
+    fixture.release.add_permits(10);
+
+    for _ in 0..5 {
+        fixture.change();
+    }
+    assert!(fixture.index_started(Duration::from_secs(5)).await);
+    assert!(!fixture.index_started(QUIET).await);
+}
+
+#[actix::test]
+async fn changes_while_indexing_go_again_once_done() {
+    let mut fixture = Fixture::new().await;
+
+    fixture.change();
+    assert!(fixture.index_started(Duration::from_secs(5)).await);
+
+    // Only one index at a time, however many changes come in meanwhile
+    fixture.change();
+    assert!(!fixture.index_started(QUIET).await);
+    fixture.change();
+    assert!(!fixture.index_started(QUIET).await);
+
+    fixture.release.add_permits(1);
+    assert!(fixture.index_started(Duration::from_secs(5)).await);
+
+    // Nothing changed during the second one
+    fixture.release.add_permits(1);
+    assert!(!fixture.index_started(QUIET).await);
+}
+
+#[actix::test]
+async fn unwatch_drops_pending_changes() {
+    let mut fixture = Fixture::new().await;
+    fixture.release.add_permits(10);
+
+    fixture.change();
+    fixture.watcher.send(Unwatch(fixture.root.path().to_path_buf())).await.unwrap();
+    assert!(!fixture.index_started(QUIET).await);
+
+    fixture.change();
+    assert!(!fixture.index_started(QUIET).await);
+}